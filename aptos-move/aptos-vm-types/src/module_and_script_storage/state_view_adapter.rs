@@ -23,7 +23,7 @@ use move_core_types::{
 use move_vm_runtime::{
     ambassador_impl_CodeStorage, ambassador_impl_ModuleStorage,
     ambassador_impl_WithRuntimeEnvironment, AsUnsyncCodeStorage, BorrowedOrOwned, CodeStorage,
-    Function, Module, ModuleStorage, RuntimeEnvironment, Script, UnsyncCodeStorage,
+    Function, Module, ModuleStorage, RuntimeEnvironment, Script, ScriptMetadata, UnsyncCodeStorage,
     UnsyncModuleStorage, WithRuntimeEnvironment,
 };
 use move_vm_types::{
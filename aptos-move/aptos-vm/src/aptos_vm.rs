@@ -121,7 +121,10 @@ use move_vm_runtime::{
     module_traversal::{TraversalContext, TraversalStorage},
     RuntimeEnvironment, WithRuntimeEnvironment,
 };
-use move_vm_types::gas::{GasMeter, UnmeteredGasMeter};
+use move_vm_types::{
+    code::ScriptCacheConfig,
+    gas::{GasMeter, UnmeteredGasMeter},
+};
 use num_cpus;
 use once_cell::sync::OnceCell;
 use std::{
@@ -136,6 +139,7 @@ static NUM_EXECUTION_SHARD: OnceCell<usize> = OnceCell::new();
 static NUM_PROOF_READING_THREADS: OnceCell<usize> = OnceCell::new();
 static DISCARD_FAILED_BLOCKS: OnceCell<bool> = OnceCell::new();
 static PROCESSED_TRANSACTIONS_DETAILED_COUNTERS: OnceCell<bool> = OnceCell::new();
+static SCRIPT_CACHE_CONFIG: OnceCell<ScriptCacheConfig> = OnceCell::new();
 
 macro_rules! deprecated_module_bundle {
     () => {
@@ -390,6 +394,22 @@ impl AptosVM {
         }
     }
 
+    /// Sets the configuration of the script cache used by parallel block execution when invoked
+    /// the first time.
+    pub fn set_script_cache_config_once(config: ScriptCacheConfig) {
+        // Only the first call succeeds, due to OnceCell semantics.
+        SCRIPT_CACHE_CONFIG.set(config).ok();
+    }
+
+    /// Returns the configuration of the script cache if already set, otherwise returns the mainnet
+    /// configuration.
+    pub fn get_script_cache_config() -> ScriptCacheConfig {
+        match SCRIPT_CACHE_CONFIG.get() {
+            Some(config) => config.clone(),
+            None => ScriptCacheConfig::mainnet(),
+        }
+    }
+
     /// Returns the internal gas schedule if it has been loaded, or an error if it hasn't.
     #[cfg(any(test, feature = "testing"))]
     pub fn gas_params_for_test(&self) -> Result<&AptosGasParameters, VMStatus> {
@@ -2848,7 +2868,10 @@ impl VMBlockExecutor for AptosVMBlockExecutor {
                 concurrency_level: AptosVM::get_concurrency_level(),
                 allow_fallback: true,
                 discard_failed_blocks: AptosVM::get_discard_failed_blocks(),
-                module_cache_config: BlockExecutorModuleCacheLocalConfig::default(),
+                module_cache_config: BlockExecutorModuleCacheLocalConfig {
+                    script_cache_config: AptosVM::get_script_cache_config(),
+                    ..BlockExecutorModuleCacheLocalConfig::default()
+                },
             },
             onchain: onchain_config,
         };
@@ -16,7 +16,7 @@ use aptos_types::{
 };
 use aptos_vm_types::module_and_script_storage::module_storage::AptosModuleStorage;
 use move_binary_format::{
-    errors::{Location, PartialVMResult, VMResult},
+    errors::{Location, PartialVMResult, VMError, VMResult},
     file_format::CompiledScript,
     CompiledModule,
 };
@@ -26,7 +26,7 @@ use move_core_types::{
 use move_vm_runtime::{Module, RuntimeEnvironment, Script, WithRuntimeEnvironment};
 use move_vm_types::code::{
     ambassador_impl_ScriptCache, Code, ModuleCache, ModuleCode, ModuleCodeBuilder, ScriptCache,
-    WithBytes,
+    ScriptHasher, WithBytes,
 };
 use std::sync::Arc;
 
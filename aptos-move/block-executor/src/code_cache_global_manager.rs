@@ -418,6 +418,7 @@ mod test {
             prefetch_framework_code: false,
             max_module_cache_size_in_bytes: 32,
             max_struct_name_index_map_num_entries: 2,
+            ..Default::default()
         };
 
         // Populate the cache for testing.
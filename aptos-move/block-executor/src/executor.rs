@@ -1112,7 +1112,12 @@ where
             "Must use sequential execution"
         );
 
-        let mut versioned_cache = MVHashMap::new();
+        let mut versioned_cache = MVHashMap::new_with_script_cache_config(
+            &self.config.local.module_cache_config.script_cache_config,
+        )
+        .map_err(|err| {
+            alert!("[BlockSTM] Invalid script cache configuration: {:?}", err);
+        })?;
         let start_shared_counter = gen_id_start_value(false);
         let shared_counter = AtomicU32::new(start_shared_counter);
 
@@ -16,6 +16,7 @@ use move_binary_format::{
     },
     CompiledModule,
 };
+use move_compiler_v2::EXPERIMENTS_METADATA_KEY;
 use move_core_types::{
     errmap::ErrorDescription,
     identifier::{IdentStr, Identifier},
@@ -268,6 +269,7 @@ pub fn check_metadata_format(
 ) -> Result<(), MalformedError> {
     let mut exist = false;
     let mut compilation_key_exist = false;
+    let mut experiments_key_exist = false;
     for data in module.metadata.iter() {
         if data.key == *APTOS_METADATA_KEY || data.key == *APTOS_METADATA_KEY_V1 {
             if exist {
@@ -291,6 +293,15 @@ pub fn check_metadata_format(
             compilation_key_exist = true;
             bcs::from_bytes::<CompilationMetadata>(&data.value)
                 .map_err(|e| MalformedError::DeserializedError(data.key.clone(), e))?;
+        } else if features.is_enabled(FeatureFlag::ACCEPT_EXPERIMENTS_METADATA)
+            && data.key == *EXPERIMENTS_METADATA_KEY
+        {
+            if experiments_key_exist {
+                return Err(MalformedError::DuplicateKey);
+            }
+            experiments_key_exist = true;
+            bcs::from_bytes::<Vec<String>>(&data.value)
+                .map_err(|e| MalformedError::DeserializedError(data.key.clone(), e))?;
         } else {
             return Err(MalformedError::UnknownKey(data.key.clone()));
         }
@@ -752,3 +763,68 @@ impl RandomnessAnnotation {
         Self { max_gas }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use move_compiler::compiled_unit::CompiledUnitEnum;
+    use move_compiler_v2::{
+        experiments_metadata, run_move_compiler_to_stderr, Experiment, Options,
+    };
+
+    fn compile_module(options: Options) -> CompiledModule {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("m.move");
+        std::fs::write(&source, "module 0x42::m { public fun f(): u64 { 1 } }").unwrap();
+        let options = Options {
+            sources: vec![source.to_string_lossy().to_string()],
+            ..options
+        };
+        let (_, units) = run_move_compiler_to_stderr(options).unwrap();
+        match units.into_iter().next().unwrap().into_compiled_unit() {
+            CompiledUnitEnum::Module(module) => module.module,
+            CompiledUnitEnum::Script(_) => panic!("expected a module"),
+        }
+    }
+
+    #[test]
+    fn test_experiments_metadata_format() {
+        let mut features = Features::default();
+        let module = compile_module(
+            Options::default().set_experiment(Experiment::ATTACH_EXPERIMENTS_METADATA, true),
+        );
+        assert!(module
+            .metadata
+            .iter()
+            .any(|data| data.key == *EXPERIMENTS_METADATA_KEY));
+
+        // The entry is only accepted once the feature is enabled.
+        features.disable(FeatureFlag::ACCEPT_EXPERIMENTS_METADATA);
+        assert!(matches!(
+            check_metadata_format(&module, &features),
+            Err(MalformedError::UnknownKey(_))
+        ));
+        features.enable(FeatureFlag::ACCEPT_EXPERIMENTS_METADATA);
+        assert!(check_metadata_format(&module, &features).is_ok());
+
+        // The entry is only accepted once, and must decode.
+        let mut duplicated = module.clone();
+        duplicated
+            .metadata
+            .push(experiments_metadata(&Options::default(), "0x42::m"));
+        assert!(matches!(
+            check_metadata_format(&duplicated, &features),
+            Err(MalformedError::DuplicateKey)
+        ));
+        let mut malformed = module;
+        for data in malformed.metadata.iter_mut() {
+            if data.key == *EXPERIMENTS_METADATA_KEY {
+                data.value = vec![0xFF];
+            }
+        }
+        assert!(matches!(
+            check_metadata_format(&malformed, &features),
+            Err(MalformedError::DeserializedError(..))
+        ));
+    }
+}
@@ -12,10 +12,12 @@ use aptos_types::{
     vm::modules::AptosModuleExtension,
     write_set::TransactionWrite,
 };
-use move_binary_format::{file_format::CompiledScript, CompiledModule};
+use move_binary_format::{errors::VMResult, file_format::CompiledScript, CompiledModule};
 use move_core_types::language_storage::ModuleId;
 use move_vm_runtime::{Module, Script};
-use move_vm_types::code::{ModuleCache, ModuleCode, SyncModuleCache, SyncScriptCache};
+use move_vm_types::code::{
+    ModuleCache, ModuleCode, ScriptCacheConfig, SyncModuleCache, SyncScriptCache,
+};
 use serde::Serialize;
 use std::{fmt::Debug, hash::Hash, sync::Arc};
 
@@ -58,18 +60,29 @@ where
     V: TransactionWrite,
     I: Copy + Clone + Eq + Hash + Debug,
 {
+    /// Returns an empty multi-version data-structure, with the script cache bounded by the
+    /// mainnet configuration.
     #[allow(clippy::new_without_default)]
     pub fn new() -> MVHashMap<K, T, V, I> {
+        Self::new_with_script_cache_config(&ScriptCacheConfig::mainnet())
+            .expect("Mainnet script cache configuration is always valid")
+    }
+
+    /// Returns an empty multi-version data-structure, with the script cache bounded and sharded
+    /// by the given configuration. Returns an error if the configuration is not valid.
+    pub fn new_with_script_cache_config(
+        config: &ScriptCacheConfig,
+    ) -> VMResult<MVHashMap<K, T, V, I>> {
         #[allow(deprecated)]
-        MVHashMap {
+        Ok(MVHashMap {
             data: VersionedData::empty(),
             group_data: VersionedGroupData::empty(),
             delayed_fields: VersionedDelayedFields::empty(),
             deprecated_modules: VersionedModules::empty(),
 
             module_cache: SyncModuleCache::empty(),
-            script_cache: SyncScriptCache::empty(),
-        }
+            script_cache: SyncScriptCache::from_config(config)?,
+        })
     }
 
     pub fn stats(&self) -> BlockStateStats {
@@ -2,7 +2,7 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use anyhow::anyhow;
-use aptos_config::config::{NodeConfig, DEFAULT_EXECUTION_CONCURRENCY_LEVEL};
+use aptos_config::config::{NodeConfig, ScriptCachePreset, DEFAULT_EXECUTION_CONCURRENCY_LEVEL};
 use aptos_storage_interface::{
     state_store::state_view::db_state_view::LatestDbStateCheckpointView, DbReaderWriter,
 };
@@ -11,6 +11,7 @@ use aptos_types::{
 };
 use aptos_vm::AptosVM;
 use aptos_vm_environment::prod_configs::set_paranoid_type_checks;
+use move_vm_types::code::ScriptCacheConfig;
 use std::cmp::min;
 
 /// Error message to display when non-production features are enabled
@@ -62,6 +63,11 @@ pub fn set_aptos_vm_configurations(node_config: &NodeConfig) {
     AptosVM::set_num_proof_reading_threads_once(
         node_config.execution.num_proof_reading_threads as usize,
     );
+    AptosVM::set_script_cache_config_once(match node_config.execution.script_cache_preset {
+        ScriptCachePreset::Mainnet => ScriptCacheConfig::mainnet(),
+        ScriptCachePreset::Testnet => ScriptCacheConfig::testnet(),
+        ScriptCachePreset::Local => ScriptCacheConfig::local(),
+    });
 
     if node_config
         .execution
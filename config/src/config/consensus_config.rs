@@ -94,6 +94,9 @@ pub struct ConsensusConfig {
     pub optimistic_sig_verification: bool,
     pub enable_round_timeout_msg: bool,
     pub enable_pipeline: bool,
+    // Reject blocks whose timestamp does not advance past their parent's on insertion into the
+    // block store, instead of relying solely on upstream proposal validation.
+    pub enforce_timestamp_monotonicity: bool,
 }
 
 /// Deprecated
@@ -325,6 +328,7 @@ impl Default for ConsensusConfig {
             optimistic_sig_verification: true,
             enable_round_timeout_msg: true,
             enable_pipeline: false,
+            enforce_timestamp_monotonicity: false,
         }
     }
 }
@@ -54,6 +54,18 @@ pub struct ExecutionConfig {
     pub transaction_filter: Filter,
     /// Used during DB bootstrapping
     pub genesis_waypoint: Option<WaypointConfig>,
+    /// Bounds of the script cache used by parallel block execution
+    pub script_cache_preset: ScriptCachePreset,
+}
+
+/// Presets for the bounds of the script cache, matching the script diversity of each network.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScriptCachePreset {
+    #[default]
+    Mainnet,
+    Testnet,
+    Local,
 }
 
 impl std::fmt::Debug for ExecutionConfig {
@@ -86,6 +98,7 @@ impl Default for ExecutionConfig {
             processed_transactions_detailed_counters: false,
             transaction_filter: Filter::empty(),
             genesis_waypoint: None,
+            script_cache_preset: ScriptCachePreset::default(),
         }
     }
 }
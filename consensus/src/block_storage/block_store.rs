@@ -2,12 +2,15 @@
 // Parts of the project are originally copyright © Meta Platforms, Inc.
 // SPDX-License-Identifier: Apache-2.0
 
+#[cfg(any(test, feature = "fuzzing"))]
+use crate::util::mock_time_service::{LogicalClock, LogicalClockTimeService};
 use crate::{
     block_storage::{
         block_tree::BlockTree,
         pending_blocks::PendingBlocks,
         tracing::{observe_block, BlockStage},
-        BlockReader,
+        BlockReader, CommitListener, CommitProofChain, PrefetchPolicy, PrunedBlockArchive,
+        PrunedBlockRetention, StorageWriteObserver,
     },
     counters,
     payload_manager::TPayloadManager,
@@ -19,6 +22,7 @@ use crate::{
 };
 use anyhow::{bail, ensure, format_err, Context};
 use aptos_bitvec::BitVec;
+use aptos_config::config::ConsensusConfig;
 use aptos_consensus_types::{
     block::Block,
     common::Round,
@@ -30,20 +34,26 @@ use aptos_consensus_types::{
     wrapped_ledger_info::WrappedLedgerInfo,
 };
 use aptos_crypto::{hash::ACCUMULATOR_PLACEHOLDER_HASH, HashValue};
-use aptos_executor_types::state_compute_result::StateComputeResult;
+use aptos_executor_types::{state_compute_result::StateComputeResult, ExecutorResult};
 use aptos_infallible::{Mutex, RwLock};
 use aptos_logger::prelude::*;
 use aptos_types::{
-    ledger_info::LedgerInfoWithSignatures, proof::accumulator::InMemoryTransactionAccumulator,
+    block_info::BlockInfo, ledger_info::LedgerInfoWithSignatures,
+    proof::accumulator::InMemoryTransactionAccumulator,
 };
-use futures::executor::block_on;
-#[cfg(test)]
-use std::collections::VecDeque;
 #[cfg(any(test, feature = "fuzzing"))]
-use std::sync::atomic::AtomicBool;
-#[cfg(any(test, feature = "fuzzing"))]
-use std::sync::atomic::Ordering;
-use std::{sync::Arc, time::Duration};
+use futures::executor::block_on;
+use futures::future::{AbortHandle, Abortable, Aborted};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use thiserror::Error;
 
 #[cfg(test)]
 #[path = "block_store_test.rs"]
@@ -52,12 +62,136 @@ mod block_store_test;
 #[path = "sync_manager.rs"]
 pub mod sync_manager;
 
+/// Number of the most recent back pressure samples kept by the `BlockStore`.
+pub const BACK_PRESSURE_HISTORY_LEN: usize = 32;
+
+/// The gap between the ordered and the commit root observed by `vote_back_pressure`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct BackPressureSample {
+    /// Local time at which the sample was taken.
+    pub timestamp: Duration,
+    /// Number of rounds the ordered root is ahead of the commit root.
+    pub pending_rounds: Round,
+}
+
+/// Summary of the state of the `BlockStore`, e.g. to report the health of the node.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct BlockStoreHealth {
+    pub ordered_round: Round,
+    pub commit_round: Round,
+    /// Number of rounds the ordered root is ahead of the commit root, i.e. the back pressure level.
+    pub pending_rounds: Round,
+    /// Whether the pending rounds exceed the vote back pressure limit.
+    pub back_pressure: bool,
+    /// Number of pruned blocks that are still kept in memory.
+    pub pruned_blocks_in_mem: usize,
+    /// See `BlockReader::commit_stall_duration`.
+    pub commit_stall_duration: Duration,
+}
+
+/// Outcome of re-sending the commits recorded in the quorum certs for execution, e.g. on recovery.
+#[derive(Debug, Default)]
+pub struct CommitAttemptSummary {
+    /// Commit rounds that were successfully sent for execution, in the order they were sent.
+    pub sent_rounds: Vec<Round>,
+    /// Commit rounds that failed to be sent for execution, together with the error.
+    pub failures: Vec<(Round, anyhow::Error)>,
+}
+
+/// Override of `vote_back_pressure` used in tests.
+#[cfg(any(test, feature = "fuzzing"))]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum BackPressureOverride {
+    Unset,
+    ForceTrue,
+    ForceFalse,
+}
+
+/// Records the local time at which the commit root advanced, which ends any commit stall.
+fn record_commit_time(last_commit_timestamp: &Mutex<Duration>, time_service: &dyn TimeService) {
+    *last_commit_timestamp.lock() = time_service.get_current_timestamp();
+    counters::COMMIT_STALL_DURATION.set(0.0);
+}
+
+/// Stores the blocks removed from the tree in the archive, if any. Must be called without holding
+/// the tree lock, as the archive may be slow (e.g. backed by disk).
+fn archive_pruned_blocks(
+    pruned_block_archive: &Option<Arc<dyn PrunedBlockArchive>>,
+    removed_blocks: &[Arc<PipelinedBlock>],
+) {
+    if let Some(pruned_block_archive) = pruned_block_archive {
+        for block in removed_blocks {
+            pruned_block_archive.store(block);
+        }
+    }
+}
+
 fn update_counters_for_ordered_blocks(ordered_blocks: &[Arc<PipelinedBlock>]) {
     for block in ordered_blocks {
         observe_block(block.block().timestamp_usecs(), BlockStage::ORDERED);
     }
 }
 
+/// Sorts the certs by the round of the block they commit. Ties on the round (possible across forks
+/// with dummy versions) are broken by the id of the committed block and then by the id of the
+/// certified block, so that recovery makes the same commit attempts across restarts.
+pub(super) fn sort_quorum_certs_for_commit(certs: &mut [QuorumCert]) {
+    certs.sort_by_key(|qc| {
+        (
+            qc.commit_info().round(),
+            qc.commit_info().id(),
+            qc.certified_block().id(),
+        )
+    });
+}
+
+/// Reasons for rejecting a quorum certificate in `insert_single_quorum_cert`. The error is
+/// returned wrapped in an `anyhow::Error`, callers can recover it via `downcast_ref`.
+#[derive(Clone, Debug, Error, Eq, PartialEq)]
+pub enum InsertQcError {
+    /// The block certified by the QC is not in the block store (yet).
+    #[error("Insert QC for block {0} without having the block in store first")]
+    MissingBlock(HashValue),
+    /// The block certified by the QC doesn't match the block in the block store.
+    #[error("QC for block {block_id} has different {certified:?} than local {local:?}")]
+    BlockInfoMismatch {
+        block_id: HashValue,
+        certified: BlockInfo,
+        local: BlockInfo,
+    },
+}
+
+/// Reasons for rejecting a block in `insert_block`. The error is returned wrapped in an
+/// `anyhow::Error`, callers can recover it via `downcast_ref`.
+#[derive(Clone, Debug, Error, Eq, PartialEq)]
+pub enum InsertBlockError {
+    /// The block is not above the ordered root, i.e. it has already been ordered past.
+    #[error("Block with old round {block_round}, ordered root round is {ordered_root_round}")]
+    OldRound {
+        block_round: Round,
+        ordered_root_round: Round,
+    },
+    /// Accepting the block would exceed the configured window of pending rounds.
+    #[error(
+        "Block round {block_round} is more than {max_pending_rounds} rounds above the commit root round {commit_root_round}"
+    )]
+    TooManyPendingRounds {
+        block_round: Round,
+        commit_root_round: Round,
+        max_pending_rounds: Round,
+    },
+}
+
+/// Compact serialized form of the block tree above the commit root, see
+/// `BlockStore::serialize_tree` and `BlockStoreBuilder::restore_from_snapshot`.
+#[derive(Deserialize, Serialize)]
+struct BlockTreeSnapshot {
+    commit_root_id: HashValue,
+    /// Blocks ordered by round, so that parents always precede their children.
+    blocks: Vec<Block>,
+    quorum_certs: Vec<QuorumCert>,
+}
+
 /// Responsible for maintaining all the blocks of payload and the dependencies of those blocks
 /// (parent and previous QC links).  It is expected to be accessed concurrently by multiple threads
 /// and is thread-safe.
@@ -86,65 +220,117 @@ pub struct BlockStore {
     vote_back_pressure_limit: Round,
     payload_manager: Arc<dyn TPayloadManager>,
     #[cfg(any(test, feature = "fuzzing"))]
-    back_pressure_for_test: AtomicBool,
-    order_vote_enabled: bool,
+    back_pressure_for_test: Mutex<BackPressureOverride>,
+    /// Can only be changed at an epoch boundary, see `set_order_vote_enabled`.
+    order_vote_enabled: AtomicBool,
     pending_blocks: Arc<Mutex<PendingBlocks>>,
     pipeline_builder: Option<PipelineBuilder>,
+    /// If set, a block is only accepted when its timestamp is strictly greater than its parent's
+    /// (or equal, for NIL and reconfiguration suffix blocks).
+    enforce_timestamp_monotonicity: bool,
+    /// Notified about committed blocks after the block tree is updated.
+    commit_listener: Option<Arc<dyn CommitListener>>,
+    /// Observes all the blocks and quorum certs right before they are saved to `storage`.
+    storage_write_observer: Option<Arc<dyn StorageWriteObserver>>,
+    /// Chooses which pruned blocks the tree keeps in memory, see `PrunedBlockRetention`.
+    pruned_block_retention: Option<Arc<dyn PrunedBlockRetention>>,
+    /// Stores the pruned blocks the tree drops from memory, see `PrunedBlockArchive`.
+    pruned_block_archive: Option<Arc<dyn PrunedBlockArchive>>,
+    /// Decides whether the payloads of inserted blocks are prefetched, see `PrefetchPolicy`.
+    prefetch_policy: Option<Arc<dyn PrefetchPolicy>>,
+    /// If set, `insert_block` rejects blocks more than this many rounds above the commit root.
+    /// Unlike the vote back pressure this is a hard limit, bounding the memory used by the tree
+    /// when commits stall. Not applied while replaying the recovered blocks.
+    max_pending_rounds: Option<Round>,
+    /// The last `BACK_PRESSURE_HISTORY_LEN` samples recorded by `vote_back_pressure`, oldest first.
+    back_pressure_history: Mutex<VecDeque<BackPressureSample>>,
+    /// Abort handles of the `finalize_order` calls in flight, by the id of the call.
+    in_flight_finalizations: Mutex<HashMap<u64, AbortHandle>>,
+    next_finalization_id: AtomicU64,
+    /// Local time at which the commit root last advanced, updated by the commit callbacks.
+    last_commit_timestamp: Arc<Mutex<Duration>>,
 }
 
 impl BlockStore {
-    pub fn new(
-        storage: Arc<dyn PersistentLivenessStorage>,
-        initial_data: RecoveryData,
-        execution_client: Arc<dyn TExecutionClient>,
-        max_pruned_blocks_in_mem: usize,
-        time_service: Arc<dyn TimeService>,
-        vote_back_pressure_limit: Round,
-        payload_manager: Arc<dyn TPayloadManager>,
-        order_vote_enabled: bool,
-        pending_blocks: Arc<Mutex<PendingBlocks>>,
-        pipeline_builder: Option<PipelineBuilder>,
-    ) -> Self {
-        let highest_2chain_tc = initial_data.highest_2chain_timeout_certificate();
-        let (root, root_metadata, blocks, quorum_certs) = initial_data.take();
-        let block_store = block_on(Self::build(
-            root,
-            root_metadata,
-            blocks,
-            quorum_certs,
-            highest_2chain_tc,
-            execution_client,
-            storage,
-            max_pruned_blocks_in_mem,
-            time_service,
-            vote_back_pressure_limit,
-            payload_manager,
-            order_vote_enabled,
-            pending_blocks,
-            pipeline_builder,
-            None,
-        ));
-        block_on(block_store.try_send_for_execution());
-        block_store
+    /// Returns a builder with the default configuration, see `BlockStoreBuilder`.
+    pub fn builder() -> BlockStoreBuilder {
+        BlockStoreBuilder::default()
     }
 
-    async fn try_send_for_execution(&self) {
+    /// Inserts the blocks of the schedule in order, each after sleeping until its recorded time.
+    /// With the logical clock of `new_deterministic`, the sleeps and the waits for the block
+    /// timestamps advance the clock instead of waiting, and run the tasks scheduled on the time
+    /// service in between, so a replay always interleaves the same way. Returns the inserted
+    /// blocks, or the first insert error.
+    pub async fn replay_inserts(
+        &self,
+        schedule: &BlockInsertSchedule,
+    ) -> anyhow::Result<Vec<Arc<PipelinedBlock>>> {
+        let mut inserted = Vec::with_capacity(schedule.inserts.len());
+        for (time, block) in &schedule.inserts {
+            if let Some(delay) = time.checked_sub(self.time_service.get_current_timestamp()) {
+                self.time_service.sleep(delay).await;
+            }
+            inserted.push(self.insert_block(block.clone()).await?);
+        }
+        Ok(inserted)
+    }
+
+    /// Serializes the blocks above the commit root together with their quorum certs into a
+    /// compact blob, which can be passed to `BlockStoreBuilder::restore_from_snapshot` for a fast
+    /// restart.
+    pub fn serialize_tree(&self) -> Vec<u8> {
+        let snapshot = {
+            let tree = self.inner.read();
+            let blocks = tree.uncommitted_blocks();
+            let quorum_certs = blocks
+                .iter()
+                .filter_map(|block| tree.get_quorum_cert_for_block(&block.id()))
+                .map(|qc| qc.as_ref().clone())
+                .collect();
+            BlockTreeSnapshot {
+                commit_root_id: tree.commit_root().id(),
+                blocks: blocks.iter().map(|block| block.block().clone()).collect(),
+                quorum_certs,
+            }
+        };
+        bcs::to_bytes(&snapshot).expect("Block tree snapshot serialization must not fail")
+    }
+
+    /// Sends the commits recorded in the quorum certs above the commit root for execution, and
+    /// reports which of them were sent and which failed. Failures do not stop later commits.
+    async fn try_send_for_execution(&self) -> CommitAttemptSummary {
         // reproduce the same batches (important for the commit phase)
         let mut certs = self.inner.read().get_all_quorum_certs_with_commit_info();
-        certs.sort_unstable_by_key(|qc| qc.commit_info().round());
+        sort_quorum_certs_for_commit(&mut certs);
+        let mut summary = CommitAttemptSummary::default();
         for qc in certs {
-            if qc.commit_info().round() > self.commit_root().round() {
+            let commit_round = qc.commit_info().round();
+            if commit_round > self.commit_root().round() {
                 info!(
                     "trying to commit to round {} with ledger info {}",
-                    qc.commit_info().round(),
+                    commit_round,
                     qc.ledger_info()
                 );
 
-                if let Err(e) = self.send_for_execution(qc.into_wrapped_ledger_info()).await {
-                    error!("Error in try-committing blocks. {}", e.to_string());
+                let result = match self
+                    .send_for_execution_impl(qc.into_wrapped_ledger_info())
+                    .await
+                {
+                    Ok(Ok(())) => Ok(()),
+                    Ok(Err(e)) => Err(anyhow::Error::from(e)),
+                    Err(e) => Err(e),
+                };
+                match result {
+                    Ok(()) => summary.sent_rounds.push(commit_round),
+                    Err(e) => {
+                        error!("Error in try-committing blocks. {}", e.to_string());
+                        summary.failures.push((commit_round, e));
+                    },
                 }
             }
         }
+        summary
     }
 
     #[allow(clippy::too_many_arguments)]
@@ -163,6 +349,14 @@ impl BlockStore {
         order_vote_enabled: bool,
         pending_blocks: Arc<Mutex<PendingBlocks>>,
         pipeline_builder: Option<PipelineBuilder>,
+        enforce_timestamp_monotonicity: bool,
+        commit_listener: Option<Arc<dyn CommitListener>>,
+        storage_write_observer: Option<Arc<dyn StorageWriteObserver>>,
+        pruned_block_retention: Option<Arc<dyn PrunedBlockRetention>>,
+        pruned_block_archive: Option<Arc<dyn PrunedBlockArchive>>,
+        prefetch_policy: Option<Arc<dyn PrefetchPolicy>>,
+        max_pending_rounds: Option<Round>,
+        last_commit_timestamp: Arc<Mutex<Duration>>,
         tree_to_replace: Option<Arc<RwLock<BlockTree>>>,
     ) -> Self {
         let RootInfo(root_block, root_qc, root_ordered_cert, root_commit_cert) = root;
@@ -214,6 +408,7 @@ impl BlockStore {
             root_commit_cert,
             max_pruned_blocks_in_mem,
             highest_2chain_timeout_cert.map(Arc::new),
+            pruned_block_retention.clone(),
         );
         let inner = if let Some(tree_to_replace) = tree_to_replace {
             *tree_to_replace.write() = tree;
@@ -222,6 +417,9 @@ impl BlockStore {
             Arc::new(RwLock::new(tree))
         };
 
+        // The new root counts as a commit, and the timestamp is shared with the block store whose
+        // tree is replaced, so that the commit callbacks of the blocks inserted below update it.
+        record_commit_time(&last_commit_timestamp, time_service.as_ref());
         let block_store = Self {
             inner,
             execution_client,
@@ -230,10 +428,21 @@ impl BlockStore {
             vote_back_pressure_limit,
             payload_manager,
             #[cfg(any(test, feature = "fuzzing"))]
-            back_pressure_for_test: AtomicBool::new(false),
-            order_vote_enabled,
+            back_pressure_for_test: Mutex::new(BackPressureOverride::Unset),
+            order_vote_enabled: AtomicBool::new(order_vote_enabled),
             pending_blocks,
             pipeline_builder,
+            enforce_timestamp_monotonicity,
+            commit_listener,
+            storage_write_observer,
+            pruned_block_retention,
+            pruned_block_archive,
+            prefetch_policy,
+            back_pressure_history: Mutex::new(VecDeque::with_capacity(BACK_PRESSURE_HISTORY_LEN)),
+            in_flight_finalizations: Mutex::new(HashMap::new()),
+            next_finalization_id: AtomicU64::new(0),
+            max_pending_rounds,
+            last_commit_timestamp,
         };
 
         for block in blocks {
@@ -253,11 +462,29 @@ impl BlockStore {
         block_store
     }
 
-    /// Send an ordered block id with the proof for execution, returns () on success or error
+    /// Send an ordered block id with the proof for execution, returns () on success or error.
+    /// Returns an error if the call is aborted via `abort_in_flight_finalizations` before the
+    /// execution client invoked the commit callback, in which case neither the ordered root nor
+    /// the pending blocks are updated. If the commit callback already ran, the commit root has
+    /// moved (and the tree was pruned below it), so the abort is ignored and the ordered root and
+    /// the pending blocks are updated as on success.
     pub async fn send_for_execution(
         &self,
         finality_proof: WrappedLedgerInfo,
     ) -> anyhow::Result<()> {
+        self.send_for_execution_impl(finality_proof)
+            .await?
+            .expect("Failed to persist commit");
+        Ok(())
+    }
+
+    /// Same as `send_for_execution`, but returns the error of the execution client instead of
+    /// panicking on it. The ordered root and the pending blocks are only updated once the blocks
+    /// are committed.
+    async fn send_for_execution_impl(
+        &self,
+        finality_proof: WrappedLedgerInfo,
+    ) -> anyhow::Result<ExecutorResult<()>> {
         let block_id_to_commit = finality_proof.commit_info().id();
         let block_to_commit = self
             .get_block(block_id_to_commit)
@@ -277,37 +504,67 @@ impl BlockStore {
 
         let block_tree = self.inner.clone();
         let storage = self.storage.clone();
+        let commit_listener = self.commit_listener.clone();
+        let pruned_block_archive = self.pruned_block_archive.clone();
+        let time_service = self.time_service.clone();
+        let last_commit_timestamp = self.last_commit_timestamp.clone();
         let finality_proof_clone = finality_proof.clone();
-        self.pending_blocks
+        let commit_round = finality_proof.commit_info().round();
+        let committed = Arc::new(AtomicBool::new(false));
+        let committed_clone = committed.clone();
+        let (abort_handle, abort_registration) = AbortHandle::new_pair();
+        let finalization_id = self.next_finalization_id.fetch_add(1, Ordering::Relaxed);
+        self.in_flight_finalizations
             .lock()
-            .gc(finality_proof.commit_info().round());
+            .insert(finalization_id, abort_handle);
         // This callback is invoked synchronously with and could be used for multiple batches of blocks.
-        self.execution_client
-            .finalize_order(
-                &blocks_to_commit,
-                finality_proof.ledger_info().clone(),
-                Box::new(
-                    move |committed_blocks: &[Arc<PipelinedBlock>],
-                          commit_decision: LedgerInfoWithSignatures| {
-                        block_tree.write().commit_callback_deprecated(
-                            storage,
-                            committed_blocks,
-                            finality_proof,
-                            commit_decision,
-                        );
-                    },
-                ),
-            )
-            .await
-            .expect("Failed to persist commit");
+        let finalize_order = self.execution_client.finalize_order(
+            &blocks_to_commit,
+            finality_proof.ledger_info().clone(),
+            Box::new(
+                move |committed_blocks: &[Arc<PipelinedBlock>],
+                      commit_decision: LedgerInfoWithSignatures| {
+                    let removed_blocks = block_tree.write().commit_callback_deprecated(
+                        storage,
+                        committed_blocks,
+                        finality_proof,
+                        commit_decision.clone(),
+                    );
+                    archive_pruned_blocks(&pruned_block_archive, &removed_blocks);
+                    committed_clone.store(true, Ordering::Release);
+                    record_commit_time(&last_commit_timestamp, time_service.as_ref());
+                    if let Some(commit_listener) = commit_listener {
+                        let committed_block_ids: Vec<_> =
+                            committed_blocks.iter().map(|block| block.id()).collect();
+                        commit_listener.on_commit(&committed_block_ids, &commit_decision);
+                    }
+                },
+            ),
+        );
+        let result = Abortable::new(finalize_order, abort_registration).await;
+        self.in_flight_finalizations.lock().remove(&finalization_id);
+        match result {
+            Ok(Ok(())) => {},
+            Ok(Err(e)) => return Ok(Err(e)),
+            // The ordered root must not fall behind the commit root moved by the callback.
+            Err(Aborted) if committed.load(Ordering::Acquire) => warn!(
+                "Finalizing order up to block {} was aborted after it was committed",
+                block_id_to_commit
+            ),
+            Err(Aborted) => bail!(
+                "Finalizing order up to block {} was aborted",
+                block_id_to_commit
+            ),
+        }
 
+        self.pending_blocks.lock().gc(commit_round);
         self.inner.write().update_ordered_root(block_to_commit.id());
         self.inner
             .write()
             .insert_ordered_cert(finality_proof_clone.clone());
         update_counters_for_ordered_blocks(&blocks_to_commit);
 
-        Ok(())
+        Ok(Ok(()))
     }
 
     pub async fn rebuild(
@@ -343,14 +600,26 @@ impl BlockStore {
             Arc::clone(&self.time_service),
             self.vote_back_pressure_limit,
             self.payload_manager.clone(),
-            self.order_vote_enabled,
+            self.order_vote_enabled(),
             self.pending_blocks.clone(),
             self.pipeline_builder.clone(),
+            self.enforce_timestamp_monotonicity,
+            self.commit_listener.clone(),
+            self.storage_write_observer.clone(),
+            self.pruned_block_retention.clone(),
+            self.pruned_block_archive.clone(),
+            self.prefetch_policy.clone(),
+            self.max_pending_rounds,
+            self.last_commit_timestamp.clone(),
             Some(self.inner.clone()),
         )
         .await;
 
-        self.try_send_for_execution().await;
+        let summary = self.try_send_for_execution().await;
+        info!(
+            "Re-sent commits after rebuilding the block tree: {:?}",
+            summary
+        );
     }
 
     /// Insert a block if it passes all validation tests.
@@ -361,18 +630,67 @@ impl BlockStore {
     /// Duplicate inserts will return the previously inserted block (
     /// note that it is considered a valid non-error case, for example, it can happen if a validator
     /// receives a certificate for a block that is currently being added).
+    /// Other blocks at or below the ordered root round are rejected with
+    /// [`InsertBlockError::OldRound`].
     pub async fn insert_block(&self, block: Block) -> anyhow::Result<Arc<PipelinedBlock>> {
         if let Some(existing_block) = self.get_block(block.id()) {
             return Ok(existing_block);
         }
-        ensure!(
-            self.inner.read().ordered_root().round() < block.round(),
-            "Block with old round"
-        );
+        let ordered_root_round = self.inner.read().ordered_root().round();
+        if block.round() <= ordered_root_round {
+            bail!(InsertBlockError::OldRound {
+                block_round: block.round(),
+                ordered_root_round,
+            });
+        }
+        if let Some(max_pending_rounds) = self.max_pending_rounds {
+            let commit_root_round = self.inner.read().commit_root().round();
+            if block.round() > commit_root_round.saturating_add(max_pending_rounds) {
+                bail!(InsertBlockError::TooManyPendingRounds {
+                    block_round: block.round(),
+                    commit_root_round,
+                    max_pending_rounds,
+                });
+            }
+        }
+        if self.enforce_timestamp_monotonicity {
+            self.verify_timestamp_monotonicity(&block)?;
+        }
+
+        let pipelined_block = self.new_pipelined_block(block)?;
 
+        // ensure local time past the block time
+        let block_time = Duration::from_micros(pipelined_block.timestamp_usecs());
+        let current_timestamp = self.time_service.get_current_timestamp();
+        if let Some(t) = block_time.checked_sub(current_timestamp) {
+            if t > Duration::from_secs(1) {
+                warn!(
+                    "Long wait time {}ms for block {}",
+                    t.as_millis(),
+                    pipelined_block.block()
+                );
+            }
+            self.time_service.wait_until(block_time).await;
+        }
+        self.save_tree(vec![pipelined_block.block().clone()], vec![])
+            .context("Insert block failed when saving block")?;
+        self.inner.write().insert_block(pipelined_block)
+    }
+
+    /// Prefetches the payload of the block (subject to the prefetch policy) and builds its
+    /// execution pipeline, without inserting it into the block tree.
+    fn new_pipelined_block(&self, block: Block) -> anyhow::Result<PipelinedBlock> {
         if let Some(payload) = block.payload() {
-            self.payload_manager
-                .prefetch_payload_data(payload, block.timestamp_usecs());
+            let should_prefetch = self
+                .prefetch_policy
+                .as_ref()
+                .map_or(true, |prefetch_policy| {
+                    prefetch_policy.should_prefetch(&block)
+                });
+            if should_prefetch {
+                self.payload_manager
+                    .prefetch_payload_data(payload, block.timestamp_usecs());
+            }
         }
 
         let pipelined_block = PipelinedBlock::new_ordered(block.clone());
@@ -386,16 +704,25 @@ impl BlockStore {
             // need weak pointer to break the cycle between block tree -> pipeline block -> callback
             let block_tree = Arc::downgrade(&self.inner);
             let storage = self.storage.clone();
+            let commit_listener = self.commit_listener.clone();
+            let pruned_block_archive = self.pruned_block_archive.clone();
+            let time_service = self.time_service.clone();
+            let last_commit_timestamp = self.last_commit_timestamp.clone();
             let id = block.id();
             let round = block.round();
             let callback = Box::new(move |commit_decision: LedgerInfoWithSignatures| {
                 if let Some(tree) = block_tree.upgrade() {
-                    tree.write().commit_callback(
+                    let removed_blocks = tree.write().commit_callback(
                         storage,
                         id,
                         round,
-                        WrappedLedgerInfo::new(VoteData::dummy(), commit_decision),
+                        WrappedLedgerInfo::new(VoteData::dummy(), commit_decision.clone()),
                     );
+                    archive_pruned_blocks(&pruned_block_archive, &removed_blocks);
+                    record_commit_time(&last_commit_timestamp, time_service.as_ref());
+                    if let Some(commit_listener) = commit_listener {
+                        commit_listener.on_commit(&[id], &commit_decision);
+                    }
                 }
             });
             pipeline_builder.build(
@@ -406,27 +733,60 @@ impl BlockStore {
                 callback,
             );
         }
+        Ok(pipelined_block)
+    }
 
-        // ensure local time past the block time
-        let block_time = Duration::from_micros(pipelined_block.timestamp_usecs());
-        let current_timestamp = self.time_service.get_current_timestamp();
-        if let Some(t) = block_time.checked_sub(current_timestamp) {
-            if t > Duration::from_secs(1) {
-                warn!(
-                    "Long wait time {}ms for block {}",
-                    t.as_millis(),
-                    pipelined_block.block()
-                );
-            }
-            self.time_service.wait_until(block_time).await;
+    /// Inserts the blocks and quorum certs of the snapshot into the block tree. Unlike
+    /// `insert_block` and `insert_single_quorum_cert`, nothing is written to storage (the snapshot
+    /// only holds blocks and quorum certs that were persisted before) and the block timestamps are
+    /// not waited for.
+    fn restore_tree(&self, snapshot: BlockTreeSnapshot) -> anyhow::Result<()> {
+        for block in snapshot.blocks {
+            let pipelined_block = self
+                .new_pipelined_block(block)
+                .context("Failed to restore block from snapshot")?;
+            self.inner
+                .write()
+                .insert_block(pipelined_block)
+                .context("Failed to restore block from snapshot")?;
         }
-        self.storage
-            .save_tree(vec![pipelined_block.block().clone()], vec![])
-            .context("Insert block failed when saving block")?;
-        self.inner.write().insert_block(pipelined_block)
+        for qc in snapshot.quorum_certs {
+            self.inner
+                .write()
+                .insert_quorum_cert(qc)
+                .context("Failed to restore quorum cert from snapshot")?;
+        }
+        Ok(())
+    }
+
+    /// Checks that the block's timestamp advances relative to the parent block it links to. NIL
+    /// blocks and reconfiguration suffix blocks must carry the same timestamp as their parent.
+    fn verify_timestamp_monotonicity(&self, block: &Block) -> anyhow::Result<()> {
+        let parent_block = self
+            .get_block(block.parent_id())
+            .ok_or_else(|| format_err!("Parent block {} not found", block.parent_id()))?;
+        if block.is_nil_block() || block.quorum_cert().certified_block().has_reconfiguration() {
+            ensure!(
+                block.timestamp_usecs() == parent_block.timestamp_usecs(),
+                "Nil/reconfig suffix block {} has timestamp {} different from parent timestamp {}",
+                block.id(),
+                block.timestamp_usecs(),
+                parent_block.timestamp_usecs()
+            );
+        } else {
+            ensure!(
+                block.timestamp_usecs() > parent_block.timestamp_usecs(),
+                "Block {} has timestamp {} not greater than parent timestamp {}",
+                block.id(),
+                block.timestamp_usecs(),
+                parent_block.timestamp_usecs()
+            );
+        }
+        Ok(())
     }
 
     /// Validates quorum certificates and inserts it into block tree assuming dependencies exist.
+    /// Validation failures are reported as [`InsertQcError`].
     pub fn insert_single_quorum_cert(&self, qc: QuorumCert) -> anyhow::Result<()> {
         // If the parent block is not the root block (i.e not None), ensure the executed state
         // of a block is consistent with its QuorumCert, otherwise persist the QuorumCert's
@@ -440,21 +800,21 @@ impl BlockStore {
                     pipelined_block
                         .block_info()
                         .match_ordered_only(qc.certified_block()),
-                    "QC for block {} has different {:?} than local {:?}",
-                    qc.certified_block().id(),
-                    qc.certified_block(),
-                    pipelined_block.block_info()
+                    InsertQcError::BlockInfoMismatch {
+                        block_id: qc.certified_block().id(),
+                        certified: qc.certified_block().clone(),
+                        local: pipelined_block.block_info(),
+                    }
                 );
                 observe_block(
                     pipelined_block.block().timestamp_usecs(),
                     BlockStage::QC_ADDED,
                 );
             },
-            None => bail!("Insert {} without having the block in store first", qc),
+            None => bail!(InsertQcError::MissingBlock(qc.certified_block().id())),
         };
 
-        self.storage
-            .save_tree(vec![], vec![qc.clone()])
+        self.save_tree(vec![], vec![qc.clone()])
             .context("Insert block failed when saving quorum")?;
         self.inner.write().insert_quorum_cert(qc)
     }
@@ -465,9 +825,7 @@ impl BlockStore {
         &self,
         tc: Arc<TwoChainTimeoutCertificate>,
     ) -> anyhow::Result<()> {
-        let cur_tc_round = self
-            .highest_2chain_timeout_cert()
-            .map_or(0, |tc| tc.round());
+        let cur_tc_round = self.highest_timeout_round();
         if tc.round() <= cur_tc_round {
             return Ok(());
         }
@@ -475,6 +833,7 @@ impl BlockStore {
             .save_highest_2chain_timeout_cert(tc.as_ref())
             .context("Timeout certificate insert failed when persisting to DB")?;
         self.inner.write().replace_2chain_timeout_cert(tc);
+        counters::HIGHEST_TIMEOUT_CERT_UPDATES.inc();
         Ok(())
     }
 
@@ -503,19 +862,60 @@ impl BlockStore {
         }
 
         // synchronously update both root_id and commit_root_id
-        let mut wlock = self.inner.write();
-        wlock.update_ordered_root(next_root_id);
-        wlock.update_commit_root(next_root_id);
-        wlock.process_pruned_blocks(id_to_remove.clone());
+        let removed_blocks = {
+            let mut wlock = self.inner.write();
+            wlock.update_ordered_root(next_root_id);
+            wlock.update_commit_root(next_root_id);
+            wlock.process_pruned_blocks(id_to_remove.clone())
+        };
+        archive_pruned_blocks(&self.pruned_block_archive, &removed_blocks);
         id_to_remove
     }
 
+    /// Forces `vote_back_pressure` to return the given value, until cleared.
     #[cfg(any(test, feature = "fuzzing"))]
     pub fn set_back_pressure_for_test(&self, back_pressure: bool) {
-        use std::sync::atomic::Ordering;
+        *self.back_pressure_for_test.lock() = if back_pressure {
+            BackPressureOverride::ForceTrue
+        } else {
+            BackPressureOverride::ForceFalse
+        };
+    }
 
-        self.back_pressure_for_test
-            .store(back_pressure, Ordering::Relaxed)
+    /// Clears the override set by `set_back_pressure_for_test`, so that `vote_back_pressure` is
+    /// computed from the ordered and commit roots again.
+    #[cfg(any(test, feature = "fuzzing"))]
+    pub fn clear_back_pressure_for_test(&self) {
+        *self.back_pressure_for_test.lock() = BackPressureOverride::Unset;
+    }
+
+    /// Returns whether order votes are enabled, i.e. whether blocks are ordered by order certs
+    /// instead of by 2-chains of quorum certs.
+    pub fn order_vote_enabled(&self) -> bool {
+        self.order_vote_enabled.load(Ordering::Acquire)
+    }
+
+    /// Enables or disables order votes, e.g. during a protocol upgrade, without rebuilding the
+    /// block store. This is only safe at an epoch boundary: nothing may be ordered beyond the
+    /// commit root, which must either start the epoch (genesis block) or end it (reconfiguration
+    /// block). Otherwise the flag is not changed and an error is returned, as the certs already
+    /// processed would be interpreted inconsistently.
+    pub fn set_order_vote_enabled(&self, enabled: bool) -> anyhow::Result<()> {
+        let commit_root = self.commit_root();
+        ensure!(
+            self.ordered_root().id() == commit_root.id(),
+            "Cannot switch order votes with blocks ordered beyond the commit root {}",
+            commit_root.id()
+        );
+        ensure!(
+            commit_root.block().is_genesis_block()
+                || commit_root.block_info().has_reconfiguration(),
+            "Cannot switch order votes outside of an epoch boundary, commit root {} at round {}",
+            commit_root.id(),
+            commit_root.round()
+        );
+        self.order_vote_enabled.store(enabled, Ordering::Release);
+        Ok(())
     }
 
     pub fn pending_blocks(&self) -> Arc<Mutex<PendingBlocks>> {
@@ -535,6 +935,396 @@ impl BlockStore {
     pub fn get_block_for_round(&self, round: Round) -> Option<Arc<PipelinedBlock>> {
         self.inner.read().get_block_for_round(round)
     }
+
+    /// Loads a block removed from memory from the pruned block archive, or returns None if there
+    /// is no archive or it does not have the block. Unlike `get_block`, this can return blocks
+    /// which are no longer part of the tree, e.g. for forensic queries.
+    pub fn archived_block(&self, block_id: HashValue) -> Option<Arc<PipelinedBlock>> {
+        self.pruned_block_archive
+            .as_ref()?
+            .load(block_id)
+            .map(Arc::new)
+    }
+
+    /// Returns the highest round among the uncommitted blocks whose payload is locally available
+    /// according to the payload manager, or None if there is no such block. The tree lock is only
+    /// held while collecting the blocks, not while querying the payload manager.
+    pub fn highest_block_with_available_payload(&self) -> Option<Round> {
+        let uncommitted_blocks = self.inner.read().uncommitted_blocks();
+        uncommitted_blocks
+            .iter()
+            .rev()
+            .find(|block| {
+                self.payload_manager
+                    .check_payload_availability(block.block())
+                    .is_ok()
+            })
+            .map(|block| block.round())
+    }
+
+    /// Checks that the commit root is not ahead of the ordered root and that it is an ancestor of
+    /// (or the same block as) the ordered root. Can be used as a periodic health check, since a
+    /// violation indicates a bug that would otherwise only surface as a confusing panic later on.
+    pub fn assert_roots_consistent(&self) -> anyhow::Result<()> {
+        let tree = self.inner.read();
+        let ordered_root = tree.ordered_root();
+        let commit_root = tree.commit_root();
+        ensure!(
+            commit_root.round() <= ordered_root.round(),
+            "Commit root {} at round {} is ahead of ordered root {} at round {}",
+            commit_root.id(),
+            commit_root.round(),
+            ordered_root.id(),
+            ordered_root.round()
+        );
+        ensure!(
+            tree.path_from_commit_root(ordered_root.id()).is_some(),
+            "Commit root {} is not an ancestor of ordered root {}",
+            commit_root.id(),
+            ordered_root.id()
+        );
+        Ok(())
+    }
+
+    /// Aborts all the `finalize_order` calls currently in flight in `send_for_execution`, e.g. on
+    /// an epoch change, so that no commit crosses the epoch boundary. See `send_for_execution` for
+    /// the semantics when the abort races with the commit callback.
+    pub fn abort_in_flight_finalizations(&self) {
+        for (_, abort_handle) in self.in_flight_finalizations.lock().drain() {
+            abort_handle.abort();
+        }
+    }
+
+    /// Exports the topology of the block tree (both the parent links and the QC links, as shown in
+    /// the diagrams above) as JSON for external visualization. Only block ids and metadata are
+    /// included.
+    pub fn tree_as_json(&self) -> serde_json::Value {
+        self.inner.read().tree_as_json()
+    }
+
+    /// Returns a rough estimate of the memory used by the in-memory block tree (including payloads,
+    /// quorum certs and pruned blocks that are still retained). The same estimate is reported in
+    /// the `BLOCK_TREE_ESTIMATED_MEMORY_BYTES` gauge whenever the tree changes.
+    pub fn estimated_memory_bytes(&self) -> usize {
+        self.inner.read().estimated_memory_bytes()
+    }
+
+    /// Returns a snapshot of the most recent back pressure samples, oldest first. Can be used to
+    /// tell whether the gap between ordering and committing is growing or shrinking.
+    pub fn recent_back_pressure(&self) -> Vec<BackPressureSample> {
+        self.back_pressure_history.lock().iter().copied().collect()
+    }
+
+    /// Returns a summary of the state of the block store, taken under a single read lock of the
+    /// block tree. Unlike `vote_back_pressure`, no back pressure sample is recorded.
+    pub fn health_summary(&self) -> BlockStoreHealth {
+        let tree = self.inner.read();
+        let ordered_round = tree.ordered_root().round();
+        let commit_round = tree.commit_root().round();
+        BlockStoreHealth {
+            ordered_round,
+            commit_round,
+            pending_rounds: ordered_round.saturating_sub(commit_round),
+            back_pressure: self.is_back_pressured(ordered_round, commit_round),
+            pruned_blocks_in_mem: tree.pruned_blocks_in_mem(),
+            commit_stall_duration: self.stall_duration(ordered_round, commit_round),
+        }
+    }
+
+    /// Returns the time since the commit root last advanced if blocks are ordered beyond it, zero
+    /// otherwise.
+    fn stall_duration(&self, ordered_round: Round, commit_round: Round) -> Duration {
+        if ordered_round > commit_round {
+            self.time_service
+                .get_current_timestamp()
+                .saturating_sub(*self.last_commit_timestamp.lock())
+        } else {
+            Duration::ZERO
+        }
+    }
+
+    /// Returns whether votes are back pressured with the given ordered and commit rounds, honoring
+    /// the override set by `set_back_pressure_for_test`.
+    fn is_back_pressured(&self, ordered_round: Round, commit_round: Round) -> bool {
+        #[cfg(any(test, feature = "fuzzing"))]
+        {
+            match *self.back_pressure_for_test.lock() {
+                BackPressureOverride::Unset => {},
+                BackPressureOverride::ForceTrue => return true,
+                BackPressureOverride::ForceFalse => return false,
+            }
+        }
+        ordered_round > self.vote_back_pressure_limit + commit_round
+    }
+
+    /// Persists the given blocks and quorum certs, notifying the storage write observer first.
+    fn save_tree(&self, blocks: Vec<Block>, quorum_certs: Vec<QuorumCert>) -> anyhow::Result<()> {
+        if let Some(storage_write_observer) = &self.storage_write_observer {
+            storage_write_observer.on_save_tree(&blocks, &quorum_certs);
+        }
+        self.storage.save_tree(blocks, quorum_certs)
+    }
+
+    fn record_back_pressure(&self, pending_rounds: Round) {
+        let sample = BackPressureSample {
+            timestamp: self.time_service.get_current_timestamp(),
+            pending_rounds,
+        };
+        let mut history = self.back_pressure_history.lock();
+        if history.len() == BACK_PRESSURE_HISTORY_LEN {
+            history.pop_front();
+        }
+        history.push_back(sample);
+    }
+}
+
+/// Builds a `BlockStore` from named settings. The storage, execution client, time service and
+/// payload manager are required, `build` fails if any of them is missing. All the other settings
+/// are optional, and default to the values of `ConsensusConfig::default()` (or are disabled).
+pub struct BlockStoreBuilder {
+    storage: Option<Arc<dyn PersistentLivenessStorage>>,
+    execution_client: Option<Arc<dyn TExecutionClient>>,
+    max_pruned_blocks_in_mem: usize,
+    time_service: Option<Arc<dyn TimeService>>,
+    vote_back_pressure_limit: Round,
+    payload_manager: Option<Arc<dyn TPayloadManager>>,
+    order_vote_enabled: bool,
+    pending_blocks: Option<Arc<Mutex<PendingBlocks>>>,
+    pipeline_builder: Option<PipelineBuilder>,
+    enforce_timestamp_monotonicity: bool,
+    commit_listener: Option<Arc<dyn CommitListener>>,
+    storage_write_observer: Option<Arc<dyn StorageWriteObserver>>,
+    max_pending_rounds: Option<Round>,
+    pruned_block_retention: Option<Arc<dyn PrunedBlockRetention>>,
+    pruned_block_archive: Option<Arc<dyn PrunedBlockArchive>>,
+    prefetch_policy: Option<Arc<dyn PrefetchPolicy>>,
+}
+
+impl Default for BlockStoreBuilder {
+    fn default() -> Self {
+        let config = ConsensusConfig::default();
+        Self {
+            storage: None,
+            execution_client: None,
+            max_pruned_blocks_in_mem: config.max_pruned_blocks_in_mem,
+            time_service: None,
+            vote_back_pressure_limit: config.vote_back_pressure_limit,
+            payload_manager: None,
+            order_vote_enabled: false,
+            pending_blocks: None,
+            pipeline_builder: None,
+            enforce_timestamp_monotonicity: config.enforce_timestamp_monotonicity,
+            commit_listener: None,
+            storage_write_observer: None,
+            max_pending_rounds: None,
+            pruned_block_retention: None,
+            pruned_block_archive: None,
+            prefetch_policy: None,
+        }
+    }
+}
+
+impl BlockStoreBuilder {
+    pub fn storage(mut self, storage: Arc<dyn PersistentLivenessStorage>) -> Self {
+        self.storage = Some(storage);
+        self
+    }
+
+    pub fn execution_client(mut self, execution_client: Arc<dyn TExecutionClient>) -> Self {
+        self.execution_client = Some(execution_client);
+        self
+    }
+
+    pub fn max_pruned_blocks_in_mem(mut self, max_pruned_blocks_in_mem: usize) -> Self {
+        self.max_pruned_blocks_in_mem = max_pruned_blocks_in_mem;
+        self
+    }
+
+    pub fn time_service(mut self, time_service: Arc<dyn TimeService>) -> Self {
+        self.time_service = Some(time_service);
+        self
+    }
+
+    pub fn vote_back_pressure_limit(mut self, vote_back_pressure_limit: Round) -> Self {
+        self.vote_back_pressure_limit = vote_back_pressure_limit;
+        self
+    }
+
+    pub fn payload_manager(mut self, payload_manager: Arc<dyn TPayloadManager>) -> Self {
+        self.payload_manager = Some(payload_manager);
+        self
+    }
+
+    pub fn order_vote_enabled(mut self, order_vote_enabled: bool) -> Self {
+        self.order_vote_enabled = order_vote_enabled;
+        self
+    }
+
+    /// Shares the pending blocks with other components, if not set the block store uses its own.
+    pub fn pending_blocks(mut self, pending_blocks: Arc<Mutex<PendingBlocks>>) -> Self {
+        self.pending_blocks = Some(pending_blocks);
+        self
+    }
+
+    pub fn pipeline_builder(mut self, pipeline_builder: PipelineBuilder) -> Self {
+        self.pipeline_builder = Some(pipeline_builder);
+        self
+    }
+
+    pub fn enforce_timestamp_monotonicity(mut self, enforce_timestamp_monotonicity: bool) -> Self {
+        self.enforce_timestamp_monotonicity = enforce_timestamp_monotonicity;
+        self
+    }
+
+    pub fn commit_listener(mut self, commit_listener: Arc<dyn CommitListener>) -> Self {
+        self.commit_listener = Some(commit_listener);
+        self
+    }
+
+    pub fn storage_write_observer(
+        mut self,
+        storage_write_observer: Arc<dyn StorageWriteObserver>,
+    ) -> Self {
+        self.storage_write_observer = Some(storage_write_observer);
+        self
+    }
+
+    /// Bounds the number of rounds the inserted blocks can be ahead of the commit root, unbounded
+    /// if not set.
+    pub fn max_pending_rounds(mut self, max_pending_rounds: Round) -> Self {
+        self.max_pending_rounds = Some(max_pending_rounds);
+        self
+    }
+
+    /// Chooses which pruned blocks are kept in memory, by default the most recently pruned ones.
+    pub fn pruned_block_retention(
+        mut self,
+        pruned_block_retention: Arc<dyn PrunedBlockRetention>,
+    ) -> Self {
+        self.pruned_block_retention = Some(pruned_block_retention);
+        self
+    }
+
+    /// Stores the pruned blocks dropped from memory, so that `get_block` can still load them. By
+    /// default they are discarded.
+    pub fn pruned_block_archive(
+        mut self,
+        pruned_block_archive: Arc<dyn PrunedBlockArchive>,
+    ) -> Self {
+        self.pruned_block_archive = Some(pruned_block_archive);
+        self
+    }
+
+    /// Decides whether the payloads of inserted blocks are prefetched, by default they all are.
+    pub fn prefetch_policy(mut self, prefetch_policy: Arc<dyn PrefetchPolicy>) -> Self {
+        self.prefetch_policy = Some(prefetch_policy);
+        self
+    }
+
+    /// Builds the block store on top of the recovered data, and sends the blocks that are already
+    /// certified to be committed for execution. Returns an error if a required field is not set.
+    pub async fn build(self, initial_data: RecoveryData) -> anyhow::Result<BlockStore> {
+        let highest_2chain_tc = initial_data.highest_2chain_timeout_certificate();
+        let (root, root_metadata, blocks, quorum_certs) = initial_data.take();
+        let block_store = self
+            .build_tree(root, root_metadata, blocks, quorum_certs, highest_2chain_tc)
+            .await?;
+        let summary = block_store.try_send_for_execution().await;
+        info!(
+            "Re-sent commits after building the block tree: {:?}",
+            summary
+        );
+        Ok(block_store)
+    }
+
+    /// Same as `build`, but takes the blocks above the root from a snapshot produced by
+    /// `BlockStore::serialize_tree` instead of replaying the blocks and quorum certs of the
+    /// recovery data. The snapshot is only accepted if it is rooted at the persisted root,
+    /// otherwise the block store is built from `initial_data` as in `build`. Returns an error if
+    /// the snapshot can't be decoded or doesn't form a valid tree on top of the root.
+    pub async fn restore_from_snapshot(
+        self,
+        snapshot: &[u8],
+        initial_data: RecoveryData,
+    ) -> anyhow::Result<BlockStore> {
+        let snapshot: BlockTreeSnapshot =
+            bcs::from_bytes(snapshot).context("Failed to deserialize block tree snapshot")?;
+        let root_id = initial_data.root_block().id();
+        if snapshot.commit_root_id != root_id {
+            warn!(
+                "Block tree snapshot root {} doesn't match persisted root {}, rebuilding from storage",
+                snapshot.commit_root_id, root_id
+            );
+            return self.build(initial_data).await;
+        }
+
+        let highest_2chain_tc = initial_data.highest_2chain_timeout_certificate();
+        let (root, root_metadata, _, _) = initial_data.take();
+        let block_store = self
+            .build_tree(root, root_metadata, vec![], vec![], highest_2chain_tc)
+            .await?;
+        block_store.restore_tree(snapshot)?;
+        let summary = block_store.try_send_for_execution().await;
+        info!(
+            "Re-sent commits after restoring from snapshot: {:?}",
+            summary
+        );
+        Ok(block_store)
+    }
+
+    async fn build_tree(
+        self,
+        root: RootInfo,
+        root_metadata: RootMetadata,
+        blocks: Vec<Block>,
+        quorum_certs: Vec<QuorumCert>,
+        highest_2chain_tc: Option<TwoChainTimeoutCertificate>,
+    ) -> anyhow::Result<BlockStore> {
+        let storage = self
+            .storage
+            .ok_or_else(|| format_err!("BlockStoreBuilder: storage is not set"))?;
+        let execution_client = self
+            .execution_client
+            .ok_or_else(|| format_err!("BlockStoreBuilder: execution client is not set"))?;
+        let time_service = self
+            .time_service
+            .ok_or_else(|| format_err!("BlockStoreBuilder: time service is not set"))?;
+        let payload_manager = self
+            .payload_manager
+            .ok_or_else(|| format_err!("BlockStoreBuilder: payload manager is not set"))?;
+        let pending_blocks = self
+            .pending_blocks
+            .unwrap_or_else(|| Arc::new(Mutex::new(PendingBlocks::new())));
+
+        let last_commit_timestamp = Arc::new(Mutex::new(time_service.get_current_timestamp()));
+        let block_store = BlockStore::build(
+            root,
+            root_metadata,
+            blocks,
+            quorum_certs,
+            highest_2chain_tc,
+            execution_client,
+            storage,
+            self.max_pruned_blocks_in_mem,
+            time_service,
+            self.vote_back_pressure_limit,
+            payload_manager,
+            self.order_vote_enabled,
+            pending_blocks,
+            self.pipeline_builder,
+            self.enforce_timestamp_monotonicity,
+            self.commit_listener,
+            self.storage_write_observer,
+            self.pruned_block_retention,
+            self.pruned_block_archive,
+            self.prefetch_policy,
+            self.max_pending_rounds,
+            last_commit_timestamp,
+            None,
+        )
+        .await;
+        Ok(block_store)
+    }
 }
 
 impl BlockReader for BlockStore {
@@ -546,6 +1336,22 @@ impl BlockReader for BlockStore {
         self.inner.read().get_block(&block_id)
     }
 
+    fn highest_timeout_round(&self) -> Round {
+        self.inner.read().highest_timeout_round()
+    }
+
+    fn next_expected_round(&self) -> Round {
+        self.inner.read().next_expected_round()
+    }
+
+    fn get_blocks(&self, block_ids: &[HashValue]) -> Vec<Option<Arc<PipelinedBlock>>> {
+        let tree = self.inner.read();
+        block_ids
+            .iter()
+            .map(|block_id| tree.get_block(block_id))
+            .collect()
+    }
+
     fn ordered_root(&self) -> Arc<PipelinedBlock> {
         self.inner.read().ordered_root()
     }
@@ -566,6 +1372,14 @@ impl BlockReader for BlockStore {
         self.inner.read().path_from_commit_root(block_id)
     }
 
+    fn path_between(
+        &self,
+        ancestor: HashValue,
+        descendant: HashValue,
+    ) -> Option<Vec<Arc<PipelinedBlock>>> {
+        self.inner.read().path_between(ancestor, descendant)
+    }
+
     #[cfg(test)]
     fn highest_certified_block(&self) -> Arc<PipelinedBlock> {
         self.inner.read().highest_certified_block()
@@ -587,6 +1401,42 @@ impl BlockReader for BlockStore {
         self.inner.read().highest_2chain_timeout_cert()
     }
 
+    fn oldest_uncommitted_round(&self) -> Option<Round> {
+        self.inner.read().oldest_uncommitted_round()
+    }
+
+    fn commit_stall_duration(&self) -> Duration {
+        self.stall_duration(self.ordered_root().round(), self.commit_root().round())
+    }
+
+    fn commit_proof_for(&self, block_id: HashValue) -> Option<CommitProofChain> {
+        self.inner.read().commit_proof_for(block_id)
+    }
+
+    fn committed_ledger_info(&self, block_id: HashValue) -> Option<LedgerInfoWithSignatures> {
+        self.inner.read().committed_ledger_info(block_id)
+    }
+
+    fn committed_blocks_since(&self, round: Round) -> (Vec<Arc<PipelinedBlock>>, bool) {
+        self.inner.read().committed_blocks_since(round)
+    }
+
+    fn qc_parent(&self, block_id: HashValue) -> Option<HashValue> {
+        self.inner.read().qc_parent(&block_id)
+    }
+
+    fn qc_path_to_root(&self, block_id: HashValue) -> Option<Vec<HashValue>> {
+        self.inner.read().qc_path_to_root(block_id)
+    }
+
+    fn equivocations_at(&self, round: Round) -> Vec<(HashValue, HashValue)> {
+        self.inner.read().equivocations_at(round)
+    }
+
+    fn blocks_missing_qc(&self) -> Vec<HashValue> {
+        self.inner.read().blocks_missing_qc()
+    }
+
     fn sync_info(&self) -> SyncInfo {
         SyncInfo::new_decoupled(
             self.highest_quorum_cert().as_ref().clone(),
@@ -599,18 +1449,18 @@ impl BlockReader for BlockStore {
 
     /// Return if the consensus is backpressured
     fn vote_back_pressure(&self) -> bool {
-        #[cfg(any(test, feature = "fuzzing"))]
-        {
-            if self.back_pressure_for_test.load(Ordering::Relaxed) {
-                return true;
-            }
-        }
         let commit_round = self.commit_root().round();
         let ordered_round = self.ordered_root().round();
         counters::OP_COUNTERS
             .gauge("back_pressure")
             .set((ordered_round - commit_round) as i64);
-        ordered_round > self.vote_back_pressure_limit + commit_round
+        // Sampled here as well, since the stall grows without the block tree changing.
+        counters::COMMIT_STALL_DURATION.set(
+            self.stall_duration(ordered_round, commit_round)
+                .as_secs_f64(),
+        );
+        self.record_back_pressure(ordered_round - commit_round);
+        self.is_back_pressured(ordered_round, commit_round)
     }
 
     fn pipeline_pending_latency(&self, proposal_timestamp: Duration) -> Duration {
@@ -712,6 +1562,23 @@ impl BlockReader for BlockStore {
     }
 }
 
+/// A recorded sequence of block inserts, each with the logical time at which it happened, see
+/// `BlockStore::replay_inserts`.
+#[cfg(any(test, feature = "fuzzing"))]
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct BlockInsertSchedule {
+    inserts: Vec<(Duration, Block)>,
+}
+
+#[cfg(any(test, feature = "fuzzing"))]
+impl BlockInsertSchedule {
+    /// Records the insert of the block at the given logical time (since UNIX_EPOCH). The inserts
+    /// must be recorded in the order they happened.
+    pub fn record(&mut self, time: Duration, block: Block) {
+        self.inserts.push((time, block));
+    }
+}
+
 #[cfg(any(test, feature = "fuzzing"))]
 impl BlockStore {
     /// Returns the number of blocks in the tree
@@ -729,6 +1596,36 @@ impl BlockStore {
         self.inner.read().pruned_blocks_in_mem()
     }
 
+    /// Builds a block store whose time is driven by the given logical clock, so that waiting for
+    /// the timestamp of a block inserted "in the future" resolves immediately and deterministically.
+    /// Recorded inserts can be replayed on it with `replay_inserts`.
+    pub fn new_deterministic(
+        storage: Arc<dyn PersistentLivenessStorage>,
+        initial_data: RecoveryData,
+        execution_client: Arc<dyn TExecutionClient>,
+        payload_manager: Arc<dyn TPayloadManager>,
+        clock: Arc<dyn LogicalClock>,
+    ) -> Self {
+        let builder = Self::builder()
+            .storage(storage)
+            .execution_client(execution_client)
+            .time_service(Arc::new(LogicalClockTimeService::new(clock)))
+            .payload_manager(payload_manager);
+        block_on(builder.build(initial_data)).expect("All required BlockStore fields are set")
+    }
+
+    /// Overrides the ordered and commit roots without any checks, e.g. to build inconsistent trees
+    pub(super) fn set_roots_for_test(&self, ordered_root_id: HashValue, commit_root_id: HashValue) {
+        let mut tree = self.inner.write();
+        tree.update_ordered_root(ordered_root_id);
+        tree.update_commit_root(commit_root_id);
+    }
+
+    /// Returns the number of `finalize_order` calls currently in flight
+    pub(super) fn num_in_flight_finalizations(&self) -> usize {
+        self.in_flight_finalizations.lock().len()
+    }
+
     /// Helper function to insert the block with the qc together
     pub async fn insert_block_with_qc(&self, block: Block) -> anyhow::Result<Arc<PipelinedBlock>> {
         self.insert_single_quorum_cert(block.quorum_cert().clone())?;
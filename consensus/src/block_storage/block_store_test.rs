@@ -3,12 +3,28 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use crate::{
-    block_storage::{block_store::sync_manager::NeedFetchResult, BlockReader},
+    block_storage::{
+        block_store::{sort_quorum_certs_for_commit, sync_manager::NeedFetchResult},
+        BlockInsertSchedule, BlockReader, BlockStore, BlockStoreBuilder, BlockStoreHealth,
+        CommitListener, InsertBlockError, InsertQcError, PrefetchPolicy, PrunedBlockArchive,
+        PrunedBlockRetention, StorageWriteObserver, BACK_PRESSURE_HISTORY_LEN,
+    },
+    payload_manager::{DirectMempoolPayloadManager, TPayloadManager},
     pending_votes::{PendingVotes, VoteReceptionResult},
+    persistent_liveness_storage::RecoveryData,
+    pipeline::execution_client::DummyExecutionClient,
     test_utils::{
-        build_empty_tree, build_simple_tree, consensus_runtime, timed_block_on, TreeInserter,
+        build_empty_tree, build_simple_tree, consensus_runtime, create_vec_signed_transactions,
+        mock_execution_client::{FinalizeOrderBehavior, MockExecutionClient},
+        timed_block_on, EmptyStorage, TreeInserter,
+    },
+    util::{
+        mock_time_service::{LogicalClock, ManualClock, SimulatedTimeService},
+        time_service::{SendTask, TimeService},
     },
 };
+use aptos_bitvec::BitVec;
+use aptos_config::config::ConsensusConfig;
 use aptos_consensus_types::{
     block::{
         block_test_utils::{
@@ -17,16 +33,201 @@ use aptos_consensus_types::{
         },
         Block,
     },
-    common::{Author, Payload},
+    common::{Author, Payload, Round},
+    pipelined_block::PipelinedBlock,
+    quorum_cert::QuorumCert,
+    timeout_2chain::{TwoChainTimeout, TwoChainTimeoutCertificate},
     vote::Vote,
     vote_data::VoteData,
+    wrapped_ledger_info::WrappedLedgerInfo,
 };
 use aptos_crypto::{HashValue, PrivateKey};
+use aptos_executor_types::ExecutorResult;
+use aptos_infallible::Mutex;
 use aptos_types::{
+    block_info::BlockInfo, ledger_info::LedgerInfoWithSignatures, transaction::SignedTransaction,
     validator_signer::ValidatorSigner, validator_verifier::random_validator_verifier,
 };
+use async_trait::async_trait;
 use proptest::prelude::*;
-use std::{cmp::min, collections::HashSet};
+use std::{
+    cmp::min,
+    collections::{HashMap, HashSet},
+    sync::Arc,
+    time::Duration,
+};
+
+/// Returns the recovery data of an empty storage, and a builder for a block store on top of it with
+/// the defaults of these tests.
+fn empty_tree_builder() -> (RecoveryData, BlockStoreBuilder) {
+    let (initial_data, storage) = EmptyStorage::start_for_testing();
+    let builder = BlockStore::builder()
+        .storage(storage)
+        .execution_client(Arc::new(DummyExecutionClient))
+        .max_pruned_blocks_in_mem(10)
+        .time_service(Arc::new(SimulatedTimeService::new()))
+        .vote_back_pressure_limit(10)
+        .payload_manager(Arc::new(DirectMempoolPayloadManager::new()))
+        .enforce_timestamp_monotonicity(false);
+    (initial_data, builder)
+}
+
+/// Returns an execution client whose `finalize_order` behaves as given.
+fn mock_execution_client(behavior: FinalizeOrderBehavior) -> Arc<MockExecutionClient> {
+    Arc::new(MockExecutionClient::with_finalize_order_behavior(behavior))
+}
+
+/// Builds an empty block store from `empty_tree_builder`, with the settings of the test applied by
+/// `configure`, and a tree inserter for it.
+async fn build_empty_tree_with(
+    configure: impl FnOnce(BlockStoreBuilder) -> BlockStoreBuilder,
+) -> (Arc<BlockStore>, TreeInserter) {
+    let (initial_data, builder) = empty_tree_builder();
+    let block_store = Arc::new(configure(builder).build(initial_data).await.unwrap());
+    let inserter = TreeInserter::new_with_store(ValidatorSigner::random(None), block_store.clone());
+    (block_store, inserter)
+}
+
+async fn restore_empty_tree_from_snapshot(
+    snapshot: &[u8],
+    storage_write_observer: Option<Arc<dyn StorageWriteObserver>>,
+) -> anyhow::Result<BlockStore> {
+    let (initial_data, mut builder) = empty_tree_builder();
+    if let Some(storage_write_observer) = storage_write_observer {
+        builder = builder.storage_write_observer(storage_write_observer);
+    }
+    builder.restore_from_snapshot(snapshot, initial_data).await
+}
+
+/// A commit listener that records all the commit notifications it receives.
+#[derive(Default)]
+struct RecordingCommitListener {
+    commits: Mutex<Vec<(Vec<HashValue>, LedgerInfoWithSignatures)>>,
+}
+
+impl CommitListener for RecordingCommitListener {
+    fn on_commit(
+        &self,
+        committed_block_ids: &[HashValue],
+        commit_decision: &LedgerInfoWithSignatures,
+    ) {
+        self.commits
+            .lock()
+            .push((committed_block_ids.to_vec(), commit_decision.clone()));
+    }
+}
+
+/// A storage write observer that records the ids of the written blocks and of the blocks certified
+/// by the written quorum certs.
+#[derive(Default)]
+struct RecordingStorageWriteObserver {
+    writes: Mutex<Vec<(Vec<HashValue>, Vec<HashValue>)>>,
+}
+
+impl StorageWriteObserver for RecordingStorageWriteObserver {
+    fn on_save_tree(&self, blocks: &[Block], quorum_certs: &[QuorumCert]) {
+        self.writes.lock().push((
+            blocks.iter().map(|block| block.id()).collect(),
+            quorum_certs
+                .iter()
+                .map(|qc| qc.certified_block().id())
+                .collect(),
+        ));
+    }
+}
+
+/// A pruned block retention that only keeps the given fork blocks.
+#[derive(Default)]
+struct ForkRetention {
+    fork_block_ids: Mutex<HashSet<HashValue>>,
+}
+
+impl PrunedBlockRetention for ForkRetention {
+    fn select_retained(
+        &self,
+        _pruned_blocks: &[Arc<PipelinedBlock>],
+        _budget: usize,
+    ) -> HashSet<HashValue> {
+        self.fork_block_ids.lock().clone()
+    }
+}
+
+#[derive(Default)]
+struct InMemoryPrunedBlockArchive {
+    blocks: Mutex<HashMap<HashValue, PipelinedBlock>>,
+}
+
+impl PrunedBlockArchive for InMemoryPrunedBlockArchive {
+    fn store(&self, block: &PipelinedBlock) {
+        self.blocks.lock().insert(block.id(), block.clone());
+    }
+
+    fn load(&self, block_id: HashValue) -> Option<PipelinedBlock> {
+        self.blocks.lock().get(&block_id).cloned()
+    }
+}
+
+/// A payload manager that only reports the payloads of the given rounds as available.
+struct PartiallyAvailablePayloadManager {
+    available_rounds: HashSet<Round>,
+}
+
+#[async_trait]
+impl TPayloadManager for PartiallyAvailablePayloadManager {
+    fn notify_commit(&self, _block_timestamp: u64, _payloads: Vec<Payload>) {}
+
+    fn prefetch_payload_data(&self, _payload: &Payload, _timestamp: u64) {}
+
+    fn check_payload_availability(&self, block: &Block) -> Result<(), BitVec> {
+        if self.available_rounds.contains(&block.round()) {
+            Ok(())
+        } else {
+            Err(BitVec::default())
+        }
+    }
+
+    async fn get_transactions(
+        &self,
+        _block: &Block,
+    ) -> ExecutorResult<(Vec<SignedTransaction>, Option<u64>)> {
+        Ok((Vec::new(), None))
+    }
+}
+
+/// Records the timestamps of the blocks whose payloads are prefetched.
+#[derive(Default)]
+struct RecordingPrefetchPayloadManager {
+    prefetched_timestamps: Mutex<Vec<u64>>,
+}
+
+#[async_trait]
+impl TPayloadManager for RecordingPrefetchPayloadManager {
+    fn notify_commit(&self, _block_timestamp: u64, _payloads: Vec<Payload>) {}
+
+    fn prefetch_payload_data(&self, _payload: &Payload, timestamp: u64) {
+        self.prefetched_timestamps.lock().push(timestamp);
+    }
+
+    fn check_payload_availability(&self, _block: &Block) -> Result<(), BitVec> {
+        Ok(())
+    }
+
+    async fn get_transactions(
+        &self,
+        _block: &Block,
+    ) -> ExecutorResult<(Vec<SignedTransaction>, Option<u64>)> {
+        Ok((Vec::new(), None))
+    }
+}
+
+/// Declines prefetching the payloads of the blocks of the given round.
+struct SkipRoundPrefetchPolicy(Round);
+
+impl PrefetchPolicy for SkipRoundPrefetchPolicy {
+    fn should_prefetch(&self, block: &Block) -> bool {
+        block.round() != self.0
+    }
+}
 
 #[tokio::test]
 async fn test_highest_block_and_quorum_cert() {
@@ -269,6 +470,38 @@ async fn test_path_from_root() {
     assert_eq!(block_store.path_from_ordered_root(genesis.id()), None);
 }
 
+#[tokio::test]
+async fn test_path_between() {
+    let mut inserter = TreeInserter::default();
+    let block_store = inserter.block_store();
+    let genesis = block_store.ordered_root();
+
+    // genesis <- a1 <- a2 <- a3
+    //              \
+    //               <- b4
+    let a1 = inserter
+        .insert_block_with_qc(certificate_for_genesis(), &genesis, 1)
+        .await;
+    let a2 = inserter.insert_block(&a1, 2, None).await;
+    let a3 = inserter.insert_block(&a2, 3, None).await;
+    let b4 = inserter.insert_block(&a1, 4, None).await;
+
+    assert_eq!(
+        block_store.path_between(a1.id(), a3.id()),
+        Some(vec![a2.clone(), a3.clone()])
+    );
+    assert_eq!(
+        block_store.path_between(genesis.id(), b4.id()),
+        Some(vec![a1.clone(), b4.clone()])
+    );
+    assert_eq!(block_store.path_between(a1.id(), a1.id()), Some(vec![]));
+
+    // Blocks on different branches, or in the wrong order, are not connected.
+    assert_eq!(block_store.path_between(a2.id(), b4.id()), None);
+    assert_eq!(block_store.path_between(a3.id(), a1.id()), None);
+    assert_eq!(block_store.path_between(HashValue::random(), a3.id()), None);
+}
+
 #[tokio::test]
 async fn test_insert_vote() {
     ::aptos_logger::Logger::init_for_testing();
@@ -346,22 +579,1423 @@ async fn test_insert_vote() {
 }
 
 #[tokio::test]
-async fn test_illegal_timestamp() {
-    let signer = ValidatorSigner::random(None);
-    let block_store = build_empty_tree();
+async fn test_insert_qc_errors() {
+    let mut inserter = TreeInserter::default();
+    let block_store = inserter.block_store();
     let genesis = block_store.ordered_root();
-    let block_with_illegal_timestamp = Block::new_proposal(
+    let a1 = inserter
+        .insert_block_with_qc(certificate_for_genesis(), &genesis, 1)
+        .await;
+
+    // QC for a block that is not in the store.
+    let missing_block_id = HashValue::random();
+    let missing_block_qc = placeholder_certificate_for_block(
+        &[inserter.signer().clone()],
+        missing_block_id,
+        a1.round() + 1,
+        a1.id(),
+        a1.round(),
+    );
+    let err = block_store
+        .insert_single_quorum_cert(missing_block_qc)
+        .unwrap_err();
+    assert_eq!(
+        err.downcast_ref::<InsertQcError>(),
+        Some(&InsertQcError::MissingBlock(missing_block_id))
+    );
+
+    // QC for a block that is in the store, but certifies a different round.
+    let mismatched_qc = placeholder_certificate_for_block(
+        &[inserter.signer().clone()],
+        a1.id(),
+        a1.round() + 1,
+        genesis.id(),
+        genesis.round(),
+    );
+    let err = block_store
+        .insert_single_quorum_cert(mismatched_qc.clone())
+        .unwrap_err();
+    match err.downcast_ref::<InsertQcError>() {
+        Some(InsertQcError::BlockInfoMismatch {
+            block_id,
+            certified,
+            local,
+        }) => {
+            assert_eq!(*block_id, a1.id());
+            assert_eq!(certified, mismatched_qc.certified_block());
+            assert_eq!(*local, a1.block_info());
+        },
+        other => panic!("Unexpected error {:?}", other),
+    }
+
+    // A matching QC is inserted successfully.
+    inserter.insert_qc_for_block(&a1, None);
+    assert!(block_store.get_quorum_cert_for_block(a1.id()).is_some());
+}
+
+#[tokio::test]
+async fn test_tree_snapshot_round_trip() {
+    let (blocks, block_store) = build_simple_tree().await;
+    let snapshot = block_store.serialize_tree();
+
+    let restored = restore_empty_tree_from_snapshot(&snapshot, None)
+        .await
+        .unwrap();
+    assert_eq!(restored.len(), block_store.len());
+    assert_eq!(restored.ordered_root(), block_store.ordered_root());
+    assert_eq!(restored.commit_root(), block_store.commit_root());
+    for block in &blocks {
+        assert_eq!(
+            restored.get_block(block.id()).unwrap().block(),
+            block.block()
+        );
+        assert_eq!(
+            restored.get_quorum_cert_for_block(block.id()),
+            block_store.get_quorum_cert_for_block(block.id())
+        );
+    }
+    assert_eq!(
+        restored.highest_quorum_cert(),
+        block_store.highest_quorum_cert()
+    );
+    // Serializing the restored tree yields the same snapshot.
+    assert_eq!(restored.serialize_tree(), snapshot);
+}
+
+#[tokio::test]
+async fn test_tree_snapshot_keeps_builder_config() {
+    let (blocks, block_store) = build_simple_tree().await;
+    let snapshot = block_store.serialize_tree();
+
+    let payload_manager = Arc::new(RecordingPrefetchPayloadManager::default());
+    let (initial_data, builder) = empty_tree_builder();
+    let restored = builder
+        .payload_manager(payload_manager.clone())
+        .prefetch_policy(Arc::new(SkipRoundPrefetchPolicy(2)))
+        .max_pending_rounds(7)
+        .restore_from_snapshot(&snapshot, initial_data)
+        .await
+        .unwrap();
+    assert_eq!(restored.max_pending_rounds, Some(7));
+
+    // The payloads of the restored blocks are prefetched according to the prefetch policy.
+    let expected_timestamps: Vec<_> = blocks[1..]
+        .iter()
+        .filter(|block| block.round() != 2)
+        .map(|block| block.timestamp_usecs())
+        .collect();
+    assert_eq!(
+        *payload_manager.prefetched_timestamps.lock(),
+        expected_timestamps
+    );
+}
+
+#[tokio::test]
+async fn test_tree_snapshot_root_mismatch() {
+    let (blocks, block_store) = build_simple_tree().await;
+    // Move the commit root to B1, so that the snapshot no longer matches the persisted genesis root.
+    block_store.prune_tree(blocks[4].id());
+    let snapshot = block_store.serialize_tree();
+
+    // The restore falls back to the (empty) persisted recovery data.
+    let restored = restore_empty_tree_from_snapshot(&snapshot, None)
+        .await
+        .unwrap();
+    assert_eq!(restored.len(), 1);
+    assert_eq!(restored.commit_root().id(), blocks[0].id());
+
+    assert!(restore_empty_tree_from_snapshot(&[0xFF], None)
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn test_commit_listener() {
+    let commit_listener = Arc::new(RecordingCommitListener::default());
+    let (block_store, mut inserter) = build_empty_tree_with(|builder| {
+        builder
+            .execution_client(mock_execution_client(
+                FinalizeOrderBehavior::CommitImmediately,
+            ))
+            .commit_listener(commit_listener.clone())
+    })
+    .await;
+
+    // genesis <- a1 <- a2 <- a3 <- a4, where the QC carried by a4 commits a2
+    let genesis = block_store.ordered_root();
+    let a1 = inserter
+        .insert_block_with_qc(certificate_for_genesis(), &genesis, 1)
+        .await;
+    let a2 = inserter.insert_block(&a1, 2, None).await;
+    let a3 = inserter.insert_block(&a2, 3, None).await;
+    assert!(commit_listener.commits.lock().is_empty());
+
+    inserter.insert_block(&a3, 4, Some(a2.block_info())).await;
+    assert_eq!(block_store.commit_root(), a2);
+    let commits = commit_listener.commits.lock();
+    assert_eq!(commits.len(), 1);
+    let (committed_block_ids, commit_decision) = &commits[0];
+    assert_eq!(committed_block_ids, &vec![a1.id(), a2.id()]);
+    assert_eq!(commit_decision.commit_info().id(), a2.id());
+}
+
+#[tokio::test]
+async fn test_insert_block_below_ordered_root() {
+    let (block_store, mut inserter) = build_empty_tree_with(|builder| {
+        builder.execution_client(mock_execution_client(
+            FinalizeOrderBehavior::CommitImmediately,
+        ))
+    })
+    .await;
+
+    // genesis <- a1 <- a2 <- a3 <- a4, where the QC carried by a4 orders a2
+    let genesis = block_store.ordered_root();
+    let a1 = inserter
+        .insert_block_with_qc(certificate_for_genesis(), &genesis, 1)
+        .await;
+    let a2 = inserter.insert_block(&a1, 2, None).await;
+    let a3 = inserter.insert_block(&a2, 3, None).await;
+    let a4 = inserter.insert_block(&a3, 4, Some(a2.block_info())).await;
+    assert_eq!(block_store.ordered_root(), a2);
+
+    // Duplicate inserts return the existing block, even at the ordered root round.
+    let existing = block_store.insert_block(a2.block().clone()).await.unwrap();
+    assert_eq!(existing, a2);
+
+    // A different block at the ordered root round is rejected.
+    let old_block = inserter.create_block_with_qc(
+        inserter.create_qc_for_block(&a1, None),
+        a1.timestamp_usecs() + 2,
+        2,
         Payload::empty(false, true),
-        0,
-        // This timestamp is illegal, it is the same as genesis
-        genesis.timestamp_usecs(),
-        certificate_for_genesis(),
-        &signer,
-        Vec::new(),
-    )
-    .unwrap();
-    let result = block_store.insert_block(block_with_illegal_timestamp).await;
-    assert!(result.is_err());
+        vec![],
+    );
+    let err = block_store.insert_block(old_block).await.unwrap_err();
+    assert_eq!(
+        err.downcast_ref::<InsertBlockError>(),
+        Some(&InsertBlockError::OldRound {
+            block_round: 2,
+            ordered_root_round: 2,
+        })
+    );
+
+    // Blocks above the ordered root are inserted as usual.
+    let a5 = inserter.insert_block(&a4, 5, None).await;
+    assert_eq!(block_store.get_block(a5.id()), Some(a5));
+}
+
+#[tokio::test]
+async fn test_max_pending_rounds() {
+    let (block_store, mut inserter) =
+        build_empty_tree_with(|builder| builder.max_pending_rounds(3)).await;
+
+    // Nothing is committed, so blocks up to round 3 fit in the window above genesis.
+    let genesis = block_store.ordered_root();
+    let a1 = inserter
+        .insert_block_with_qc(certificate_for_genesis(), &genesis, 1)
+        .await;
+    let a2 = inserter.insert_block(&a1, 2, None).await;
+    let a3 = inserter.insert_block(&a2, 3, None).await;
+
+    let a4 = inserter.create_block_with_qc(
+        inserter.create_qc_for_block(&a3, None),
+        a3.timestamp_usecs() + 1,
+        4,
+        Payload::empty(false, true),
+        vec![],
+    );
+    let err = block_store.insert_block(a4.clone()).await.unwrap_err();
+    assert_eq!(
+        err.downcast_ref::<InsertBlockError>(),
+        Some(&InsertBlockError::TooManyPendingRounds {
+            block_round: 4,
+            commit_root_round: 0,
+            max_pending_rounds: 3,
+        })
+    );
+    assert!(block_store.get_block(a4.id()).is_none());
+}
+
+#[tokio::test]
+async fn test_abort_in_flight_finalizations() {
+    let (block_store, mut inserter) = build_empty_tree_with(|builder| {
+        builder.execution_client(mock_execution_client(FinalizeOrderBehavior::Stall))
+    })
+    .await;
+
+    // genesis <- a1 <- a2 <- a3, where the QC for a3 commits a2
+    let genesis = block_store.ordered_root();
+    let a1 = inserter
+        .insert_block_with_qc(certificate_for_genesis(), &genesis, 1)
+        .await;
+    let a2 = inserter.insert_block(&a1, 2, None).await;
+    let a3 = inserter.insert_block(&a2, 3, None).await;
+    let qc = inserter.create_qc_for_block(&a3, Some(a2.block_info()));
+
+    let store = block_store.clone();
+    let finalization = tokio::spawn(async move {
+        store
+            .send_for_execution(qc.into_wrapped_ledger_info())
+            .await
+    });
+    while block_store.num_in_flight_finalizations() == 0 {
+        tokio::task::yield_now().await;
+    }
+
+    block_store.abort_in_flight_finalizations();
+    assert!(finalization.await.unwrap().is_err());
+    assert_eq!(block_store.ordered_root(), genesis);
+    assert_eq!(block_store.num_in_flight_finalizations(), 0);
+}
+
+#[tokio::test]
+async fn test_abort_finalization_after_commit() {
+    let (block_store, mut inserter) = build_empty_tree_with(|builder| {
+        builder.execution_client(mock_execution_client(
+            FinalizeOrderBehavior::CommitThenStall,
+        ))
+    })
+    .await;
+
+    // genesis <- a1 <- a2 <- a3, where the QC for a3 commits a2
+    let genesis = block_store.ordered_root();
+    let a1 = inserter
+        .insert_block_with_qc(certificate_for_genesis(), &genesis, 1)
+        .await;
+    let a2 = inserter.insert_block(&a1, 2, None).await;
+    let a3 = inserter.insert_block(&a2, 3, None).await;
+    let qc = inserter.create_qc_for_block(&a3, Some(a2.block_info()));
+
+    let store = block_store.clone();
+    let finalization = tokio::spawn(async move {
+        store
+            .send_for_execution(qc.into_wrapped_ledger_info())
+            .await
+    });
+    while block_store.commit_root() != a2 {
+        tokio::task::yield_now().await;
+    }
+
+    // The commit already pruned the tree up to a2, so the ordered root follows it.
+    block_store.abort_in_flight_finalizations();
+    finalization.await.unwrap().unwrap();
+    assert_eq!(block_store.ordered_root(), a2);
+    assert_eq!(block_store.commit_root(), a2);
+    assert!(block_store.assert_roots_consistent().is_ok());
+    assert_eq!(block_store.num_in_flight_finalizations(), 0);
+
+    // Later commits go through as usual.
+    let a4 = inserter.insert_block(&a3, 4, None).await;
+    let qc = inserter.create_qc_for_block(&a4, Some(a3.block_info()));
+    let store = block_store.clone();
+    let finalization = tokio::spawn(async move {
+        store
+            .send_for_execution(qc.into_wrapped_ledger_info())
+            .await
+    });
+    while block_store.commit_root() != a3 {
+        tokio::task::yield_now().await;
+    }
+    block_store.abort_in_flight_finalizations();
+    finalization.await.unwrap().unwrap();
+    assert_eq!(block_store.ordered_root(), a3);
+}
+
+#[tokio::test]
+async fn test_try_send_for_execution_summary() {
+    let (block_store, mut inserter) = build_empty_tree_with(|builder| {
+        builder.execution_client(mock_execution_client(FinalizeOrderBehavior::FailRounds(
+            HashSet::from([2]),
+        )))
+    })
+    .await;
+
+    // genesis <- a1 <- a2 <- a3 <- a4 <- a5, where the QCs carried by a3, a4 and a5 commit a1, a2
+    // and a3 respectively.
+    let genesis = block_store.ordered_root();
+    let a1 = inserter
+        .insert_block_with_qc(certificate_for_genesis(), &genesis, 1)
+        .await;
+    let a2 = inserter.insert_block(&a1, 2, None).await;
+    let a3 = inserter.insert_block(&a2, 3, Some(a1.block_info())).await;
+    let a4 = inserter.insert_block(&a3, 4, Some(a2.block_info())).await;
+    inserter.insert_block(&a4, 5, Some(a3.block_info())).await;
+
+    // The commit of round 2 fails, but the commit of round 3 still goes through.
+    let summary = block_store.try_send_for_execution().await;
+    assert_eq!(summary.sent_rounds, vec![1, 3]);
+    assert_eq!(summary.failures.len(), 1);
+    assert_eq!(summary.failures[0].0, 2);
+    assert_eq!(block_store.ordered_root(), a3);
+    assert_eq!(block_store.commit_root(), a3);
+}
+
+#[test]
+fn test_sort_quorum_certs_for_commit() {
+    let signer = ValidatorSigner::random(None);
+    let block_info = |round, id| BlockInfo::new(1, round, id, HashValue::zero(), 0, 0, None);
+    let certify = |certified_round, committed: BlockInfo| {
+        gen_test_certificate(
+            &[signer.clone()],
+            block_info(certified_round, HashValue::random()),
+            block_info(certified_round - 1, HashValue::random()),
+            Some(committed),
+        )
+    };
+    // Two forks committing different blocks at round 3, and one cert committing round 2.
+    let mut fork_ids = [HashValue::random(), HashValue::random()];
+    fork_ids.sort();
+    let expected = vec![
+        certify(4, block_info(2, HashValue::random())),
+        certify(5, block_info(3, fork_ids[0])),
+        certify(5, block_info(3, fork_ids[1])),
+    ];
+
+    for order in [[0, 1, 2], [2, 1, 0], [1, 2, 0]] {
+        let mut certs: Vec<_> = order.iter().map(|i| expected[*i].clone()).collect();
+        sort_quorum_certs_for_commit(&mut certs);
+        assert_eq!(certs, expected);
+    }
+}
+
+#[tokio::test]
+async fn test_commit_proof_for() {
+    let (block_store, mut inserter) = build_empty_tree_with(|builder| {
+        builder.execution_client(mock_execution_client(
+            FinalizeOrderBehavior::CommitImmediately,
+        ))
+    })
+    .await;
+
+    // genesis <- a1 <- a2 <- a3 <- a4, where the QC carried by a4 commits a2
+    let genesis = block_store.ordered_root();
+    let a1 = inserter
+        .insert_block_with_qc(certificate_for_genesis(), &genesis, 1)
+        .await;
+    let a2 = inserter.insert_block(&a1, 2, None).await;
+    let a3 = inserter.insert_block(&a2, 3, None).await;
+    let a4 = inserter.insert_block(&a3, 4, Some(a2.block_info())).await;
+    assert_eq!(block_store.commit_root(), a2);
+
+    // a1 is only covered by the QC chain of the cert committing a2.
+    let proof = block_store.commit_proof_for(a1.id()).unwrap();
+    assert_eq!(proof.quorum_cert.certified_block().id(), a1.id());
+    assert_eq!(proof.ordered_cert.commit_info().id(), a2.id());
+    assert_eq!(proof.commit_cert.commit_info().id(), a2.id());
+    let proof = block_store.commit_proof_for(a2.id()).unwrap();
+    assert_eq!(proof.quorum_cert.certified_block().id(), a2.id());
+    assert_eq!(proof.commit_cert.commit_info().id(), a2.id());
+    // Neither the uncommitted a3 nor b5, forking off it, are covered by a cert.
+    let b5 = inserter.insert_block(&a3, 5, None).await;
+    assert!(block_store.commit_proof_for(a3.id()).is_none());
+    assert!(block_store.commit_proof_for(b5.id()).is_none());
+
+    // Once a3 is committed, a2 keeps the cert committing exactly it, consistently with
+    // `committed_ledger_info`, while a3 gets the highest commit cert.
+    let a5 = inserter.insert_block(&a4, 6, Some(a3.block_info())).await;
+    assert_eq!(block_store.commit_root(), a3);
+    let proof = block_store.commit_proof_for(a2.id()).unwrap();
+    assert_eq!(proof.commit_cert.commit_info().id(), a2.id());
+    assert_eq!(
+        proof.commit_cert.ledger_info(),
+        &block_store.committed_ledger_info(a2.id()).unwrap()
+    );
+    let proof = block_store.commit_proof_for(a3.id()).unwrap();
+    assert_eq!(proof.commit_cert.commit_info().id(), a3.id());
+    assert_eq!(proof.ordered_cert.commit_info().id(), a3.id());
+    assert!(block_store.commit_proof_for(b5.id()).is_none());
+
+    // Keep committing until a1 is pruned from memory.
+    let (mut parent, mut grandparent) = (a5, a4);
+    for round in 7..20 {
+        let block = inserter
+            .insert_block(&parent, round, Some(grandparent.block_info()))
+            .await;
+        grandparent = std::mem::replace(&mut parent, block);
+    }
+    assert!(block_store.get_block(a1.id()).is_none());
+    assert!(block_store.commit_proof_for(a1.id()).is_none());
+}
+
+#[tokio::test]
+async fn test_committed_ledger_info() {
+    let (block_store, mut inserter) = build_empty_tree_with(|builder| {
+        builder.execution_client(mock_execution_client(
+            FinalizeOrderBehavior::CommitImmediately,
+        ))
+    })
+    .await;
+
+    //       ╭--> a2 --> a3 --> a4
+    // a1 ---|
+    //       ╰--> b3
+    // where the QC carried by a3 commits a1.
+    let genesis = block_store.ordered_root();
+    let a1 = inserter
+        .insert_block_with_qc(certificate_for_genesis(), &genesis, 1)
+        .await;
+    let a2 = inserter.insert_block(&a1, 2, None).await;
+    let b3 = inserter.insert_block(&a1, 3, None).await;
+    let a3 = inserter.insert_block(&a2, 4, Some(a1.block_info())).await;
+    assert_eq!(block_store.commit_root(), a1);
+
+    let ledger_info = block_store.committed_ledger_info(a1.id()).unwrap();
+    assert_eq!(ledger_info.commit_info().id(), a1.id());
+    assert!(block_store.committed_ledger_info(a2.id()).is_none());
+    assert!(block_store.committed_ledger_info(b3.id()).is_none());
+
+    // Once a2 is committed as well, the ledger info committing a1 is still returned for it.
+    inserter.insert_block(&a3, 5, Some(a2.block_info())).await;
+    assert_eq!(block_store.commit_root(), a2);
+    let ledger_info = block_store.committed_ledger_info(a1.id()).unwrap();
+    assert_eq!(ledger_info.commit_info().id(), a1.id());
+    let ledger_info = block_store.committed_ledger_info(a2.id()).unwrap();
+    assert_eq!(ledger_info.commit_info().id(), a2.id());
+    assert!(block_store.committed_ledger_info(b3.id()).is_none());
+}
+
+#[tokio::test]
+async fn test_pruned_block_retention() {
+    let retention = Arc::new(ForkRetention::default());
+    let (block_store, mut inserter) = build_empty_tree_with(|builder| {
+        builder
+            .execution_client(mock_execution_client(
+                FinalizeOrderBehavior::CommitImmediately,
+            ))
+            .max_pruned_blocks_in_mem(2)
+            .pruned_block_retention(retention.clone())
+    })
+    .await;
+
+    //       ╭--> b1
+    // genesis--> a1--> a2--> a3--> a4, where the QC carried by a4 commits a2
+    //             ╰--> c1
+    let genesis = block_store.ordered_root();
+    let a1 = inserter
+        .insert_block_with_qc(certificate_for_genesis(), &genesis, 1)
+        .await;
+    let a2 = inserter.insert_block(&a1, 2, None).await;
+    let a3 = inserter.insert_block(&a2, 3, None).await;
+    let b1 = inserter
+        .insert_block_with_qc(certificate_for_genesis(), &genesis, 4)
+        .await;
+    let c1 = inserter.insert_block(&a1, 5, None).await;
+    *retention.fork_block_ids.lock() = HashSet::from([b1.id(), c1.id()]);
+
+    // Committing a2 prunes genesis, a1, b1 and c1, only the fork blocks are kept.
+    inserter.insert_block(&a3, 6, Some(a2.block_info())).await;
+    assert_eq!(block_store.commit_root(), a2);
+    assert!(block_store.get_block(genesis.id()).is_none());
+    assert!(block_store.get_block(a1.id()).is_none());
+    assert!(block_store.get_block(b1.id()).is_some());
+    assert!(block_store.get_block(c1.id()).is_some());
+}
+
+#[tokio::test]
+async fn test_pruned_block_archive() {
+    let archive = Arc::new(InMemoryPrunedBlockArchive::default());
+    let (block_store, mut inserter) = build_empty_tree_with(|builder| {
+        builder
+            .execution_client(mock_execution_client(
+                FinalizeOrderBehavior::CommitImmediately,
+            ))
+            .max_pruned_blocks_in_mem(1)
+            .pruned_block_archive(archive.clone())
+    })
+    .await;
+
+    // genesis <- a1 <- a2 <- a3 <- a4, where the QC carried by a4 commits a2
+    let genesis = block_store.ordered_root();
+    let a1 = inserter
+        .insert_block_with_qc(certificate_for_genesis(), &genesis, 1)
+        .await;
+    let a2 = inserter.insert_block(&a1, 2, None).await;
+    let a3 = inserter.insert_block(&a2, 3, None).await;
+    assert!(archive.blocks.lock().is_empty());
+
+    // Committing a2 prunes genesis and a1, only one of which is kept in memory. The other one is
+    // archived: get_block no longer returns it, but archived_block does.
+    inserter.insert_block(&a3, 4, Some(a2.block_info())).await;
+    assert_eq!(block_store.commit_root(), a2);
+    assert_eq!(archive.blocks.lock().len(), 1);
+    let (in_memory, archived) = if block_store.block_exists(genesis.id()) {
+        (genesis.clone(), a1.clone())
+    } else {
+        (a1.clone(), genesis.clone())
+    };
+    assert!(block_store.block_exists(in_memory.id()));
+    assert!(block_store.archived_block(in_memory.id()).is_none());
+    assert!(block_store.get_block(archived.id()).is_none());
+    assert_eq!(
+        block_store.archived_block(archived.id()).unwrap().id(),
+        archived.id()
+    );
+    assert!(block_store.archived_block(HashValue::random()).is_none());
+
+    // An archived block is still rejected as too old when inserted again.
+    let error = block_store
+        .insert_block(archived.block().clone())
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error.downcast_ref::<InsertBlockError>(),
+        Some(InsertBlockError::OldRound { .. })
+    ));
+}
+
+#[tokio::test]
+async fn test_roots_consistency() {
+    let (blocks, block_store) = build_simple_tree().await;
+    assert!(block_store.assert_roots_consistent().is_ok());
+
+    // Ordered root a3, commit root a1.
+    block_store.set_roots_for_test(blocks[3].id(), blocks[1].id());
+    assert!(block_store.assert_roots_consistent().is_ok());
+
+    // Commit root a2 is ahead of the ordered root a1.
+    block_store.set_roots_for_test(blocks[1].id(), blocks[2].id());
+    assert!(block_store.assert_roots_consistent().is_err());
+
+    // Commit root a1 is on a different branch than the ordered root b1.
+    block_store.set_roots_for_test(blocks[4].id(), blocks[1].id());
+    assert!(block_store.assert_roots_consistent().is_err());
+
+    // Ordered root b1, commit root genesis.
+    block_store.set_roots_for_test(blocks[4].id(), blocks[0].id());
+    assert!(block_store.assert_roots_consistent().is_ok());
+}
+
+#[tokio::test]
+async fn test_oldest_uncommitted_round() {
+    let (blocks, block_store) = build_simple_tree().await;
+    assert_eq!(block_store.oldest_uncommitted_round(), None);
+
+    // Ordered root a3, commit root genesis.
+    block_store.set_roots_for_test(blocks[3].id(), blocks[0].id());
+    assert_eq!(block_store.oldest_uncommitted_round(), Some(1));
+
+    // Ordered root a3, commit root a1.
+    block_store.set_roots_for_test(blocks[3].id(), blocks[1].id());
+    assert_eq!(block_store.oldest_uncommitted_round(), Some(2));
+}
+
+#[tokio::test]
+async fn test_commit_stall_duration() {
+    let time_service = SimulatedTimeService::new();
+    let (block_store, mut inserter) = build_empty_tree_with(|builder| {
+        builder
+            .execution_client(mock_execution_client(
+                FinalizeOrderBehavior::CommitImmediately,
+            ))
+            .time_service(Arc::new(time_service.clone()))
+    })
+    .await;
+
+    // genesis <- a1 <- a2 <- a3
+    let genesis = block_store.ordered_root();
+    let a1 = inserter
+        .insert_block_with_qc(certificate_for_genesis(), &genesis, 1)
+        .await;
+    let a2 = inserter.insert_block(&a1, 2, None).await;
+    let a3 = inserter.insert_block(&a2, 3, None).await;
+    assert_eq!(block_store.commit_stall_duration(), Duration::ZERO);
+
+    // Ordered root a1, commit root genesis: the stall grows as time passes.
+    block_store.set_roots_for_test(a1.id(), genesis.id());
+    let stall_duration = block_store.commit_stall_duration();
+    time_service.sleep(Duration::from_secs(5)).await;
+    assert_eq!(
+        block_store.commit_stall_duration(),
+        stall_duration + Duration::from_secs(5)
+    );
+
+    // The QC carried by a4 commits a2, which resets the stall.
+    inserter.insert_block(&a3, 4, Some(a2.block_info())).await;
+    assert_eq!(block_store.commit_root(), a2);
+    assert_eq!(block_store.commit_stall_duration(), Duration::ZERO);
+
+    // Ordered root a3, commit root a2.
+    block_store.set_roots_for_test(a3.id(), a2.id());
+    assert_eq!(block_store.commit_stall_duration(), Duration::ZERO);
+    time_service.sleep(Duration::from_secs(1)).await;
+    assert_eq!(block_store.commit_stall_duration(), Duration::from_secs(1));
+}
+
+#[tokio::test]
+async fn test_commit_stall_duration_after_rebuild() {
+    let time_service = SimulatedTimeService::new();
+    let (block_store, mut inserter) = build_empty_tree_with(|builder| {
+        builder
+            .execution_client(mock_execution_client(
+                FinalizeOrderBehavior::CommitImmediately,
+            ))
+            .time_service(Arc::new(time_service.clone()))
+    })
+    .await;
+
+    // genesis <- a1 <- a2, ordered root a1 and commit root genesis for 5 seconds.
+    let genesis = block_store.ordered_root();
+    let a1 = inserter
+        .insert_block_with_qc(certificate_for_genesis(), &genesis, 1)
+        .await;
+    inserter.insert_block(&a1, 2, None).await;
+    block_store.set_roots_for_test(a1.id(), genesis.id());
+    time_service.sleep(Duration::from_secs(5)).await;
+    assert!(block_store.commit_stall_duration() >= Duration::from_secs(5));
+
+    // Rebuilding sets a new commit root, which resets the stall.
+    let (root, root_metadata, blocks, quorum_certs) = empty_tree_builder().0.take();
+    block_store
+        .rebuild(root, root_metadata, blocks, quorum_certs)
+        .await;
+    let genesis = block_store.ordered_root();
+    let a1 = inserter
+        .insert_block_with_qc(certificate_for_genesis(), &genesis, 1)
+        .await;
+    let a2 = inserter.insert_block(&a1, 2, None).await;
+    block_store.set_roots_for_test(a1.id(), genesis.id());
+    assert_eq!(block_store.commit_stall_duration(), Duration::ZERO);
+    time_service.sleep(Duration::from_secs(2)).await;
+    assert_eq!(block_store.commit_stall_duration(), Duration::from_secs(2));
+
+    // The commits after the rebuild keep resetting it.
+    inserter.insert_block(&a2, 3, Some(a1.block_info())).await;
+    assert_eq!(block_store.commit_root(), a1);
+    block_store.set_roots_for_test(a2.id(), a1.id());
+    assert_eq!(block_store.commit_stall_duration(), Duration::ZERO);
+}
+
+#[tokio::test]
+async fn test_health_summary() {
+    let time_service = SimulatedTimeService::new();
+    let (block_store, mut inserter) = build_empty_tree_with(|builder| {
+        builder
+            .execution_client(mock_execution_client(
+                FinalizeOrderBehavior::CommitImmediately,
+            ))
+            .time_service(Arc::new(time_service.clone()))
+            .vote_back_pressure_limit(1)
+    })
+    .await;
+    assert_eq!(block_store.health_summary(), BlockStoreHealth {
+        ordered_round: 0,
+        commit_round: 0,
+        pending_rounds: 0,
+        back_pressure: false,
+        pruned_blocks_in_mem: 0,
+        commit_stall_duration: Duration::ZERO,
+    });
+
+    // genesis <- a1 <- a2 <- a3 <- a4, where the QC carried by a4 commits a1 and prunes genesis
+    let genesis = block_store.ordered_root();
+    let a1 = inserter
+        .insert_block_with_qc(certificate_for_genesis(), &genesis, 1)
+        .await;
+    let a2 = inserter.insert_block(&a1, 2, None).await;
+    let a3 = inserter.insert_block(&a2, 3, None).await;
+    inserter.insert_block(&a3, 4, Some(a1.block_info())).await;
+
+    // Ordered root a3, commit root a1.
+    block_store.set_roots_for_test(a3.id(), a1.id());
+    let commit_stall_duration = block_store.commit_stall_duration();
+    time_service.sleep(Duration::from_secs(3)).await;
+    assert_eq!(block_store.health_summary(), BlockStoreHealth {
+        ordered_round: 3,
+        commit_round: 1,
+        pending_rounds: 2,
+        back_pressure: true,
+        pruned_blocks_in_mem: 1,
+        commit_stall_duration: commit_stall_duration + Duration::from_secs(3),
+    });
+    assert_eq!(
+        block_store.health_summary().commit_stall_duration,
+        block_store.commit_stall_duration()
+    );
+
+    // The back pressure follows `vote_back_pressure`, including its override.
+    block_store.set_back_pressure_for_test(false);
+    assert!(!block_store.health_summary().back_pressure);
+    assert!(!block_store.vote_back_pressure());
+}
+
+#[tokio::test]
+async fn test_prefetch_policy() {
+    let payload_manager = Arc::new(RecordingPrefetchPayloadManager::default());
+    let (block_store, mut inserter) = build_empty_tree_with(|builder| {
+        builder
+            .payload_manager(payload_manager.clone())
+            .prefetch_policy(Arc::new(SkipRoundPrefetchPolicy(2)))
+    })
+    .await;
+
+    // genesis <- a1 <- a2 <- a3
+    let genesis = block_store.ordered_root();
+    let a1 = inserter
+        .insert_block_with_qc(certificate_for_genesis(), &genesis, 1)
+        .await;
+    let a2 = inserter.insert_block(&a1, 2, None).await;
+    let a3 = inserter.insert_block(&a2, 3, None).await;
+    assert!(a2.payload().is_some());
+    assert_eq!(*payload_manager.prefetched_timestamps.lock(), vec![
+        a1.timestamp_usecs(),
+        a3.timestamp_usecs()
+    ]);
+}
+
+#[tokio::test]
+async fn test_committed_blocks_since() {
+    let (block_store, mut inserter) = build_empty_tree_with(|builder| {
+        builder
+            .execution_client(mock_execution_client(
+                FinalizeOrderBehavior::CommitImmediately,
+            ))
+            .max_pruned_blocks_in_mem(1)
+    })
+    .await;
+
+    // genesis <- a1 <- a2 <- a3 <- a4 <- a5, where the QCs carried by a4 and a5 commit a2 and a3
+    let genesis = block_store.ordered_root();
+    let a1 = inserter
+        .insert_block_with_qc(certificate_for_genesis(), &genesis, 1)
+        .await;
+    let a2 = inserter.insert_block(&a1, 2, None).await;
+    let a3 = inserter.insert_block(&a2, 3, None).await;
+    assert_eq!(block_store.committed_blocks_since(0), (vec![], false));
+
+    let a4 = inserter.insert_block(&a3, 4, Some(a2.block_info())).await;
+    assert_eq!(
+        block_store.committed_blocks_since(0),
+        (vec![a1.clone(), a2.clone()], false)
+    );
+
+    // Only a2 is kept in memory after being pruned, a1 is gone.
+    inserter.insert_block(&a4, 5, Some(a3.block_info())).await;
+    assert_eq!(block_store.commit_root(), a3);
+    assert_eq!(
+        block_store.committed_blocks_since(0),
+        (vec![a2.clone(), a3.clone()], true)
+    );
+    assert_eq!(
+        block_store.committed_blocks_since(1),
+        (vec![a2.clone(), a3.clone()], false)
+    );
+    assert_eq!(block_store.committed_blocks_since(2), (vec![a3], false));
+    assert_eq!(block_store.committed_blocks_since(3), (vec![], false));
+}
+
+#[tokio::test]
+async fn test_qc_links() {
+    let (blocks, block_store) = build_simple_tree().await;
+    let genesis = &blocks[0];
+    let (a1, a2, a3) = (&blocks[1], &blocks[2], &blocks[3]);
+
+    // For proposals, the QC links match the parent links.
+    assert_eq!(block_store.qc_parent(a3.id()), Some(a2.id()));
+    assert_eq!(
+        block_store.qc_path_to_root(a3.id()),
+        Some(vec![a3.id(), a2.id(), a1.id(), genesis.id()])
+    );
+    assert_eq!(
+        block_store.qc_path_to_root(genesis.id()),
+        Some(vec![genesis.id()])
+    );
+
+    // A DAG block carries a dummy QC, so its QC link leaves the tree.
+    let dag_block = Block::new_for_dag(
+        genesis.epoch(),
+        7,
+        genesis.timestamp_usecs() + 1,
+        vec![],
+        Payload::empty(false, true),
+        Author::random(),
+        vec![],
+        a3.id(),
+        BitVec::default(),
+        vec![],
+    );
+    let dag_block = block_store.insert_block(dag_block).await.unwrap();
+    assert_eq!(dag_block.parent_id(), a3.id());
+    assert_eq!(
+        block_store.qc_parent(dag_block.id()),
+        Some(HashValue::zero())
+    );
+    assert_eq!(block_store.qc_path_to_root(dag_block.id()), None);
+    assert_eq!(
+        block_store.path_from_commit_root(dag_block.id()),
+        Some(vec![a1.clone(), a2.clone(), a3.clone(), dag_block.clone()])
+    );
+
+    assert_eq!(block_store.qc_parent(HashValue::random()), None);
+    assert_eq!(block_store.qc_path_to_root(HashValue::random()), None);
+}
+
+#[tokio::test]
+async fn test_equivocations_at() {
+    let mut inserter = TreeInserter::default();
+    let block_store = inserter.block_store();
+    let genesis = block_store.ordered_root();
+    let a1 = inserter
+        .insert_block_with_qc(certificate_for_genesis(), &genesis, 1)
+        .await;
+    assert!(block_store.equivocations_at(1).is_empty());
+
+    // Two different blocks proposed by the same author at round 2.
+    let mut equivocating_ids = vec![];
+    for timestamp_offset in 1..=2 {
+        let block = inserter.create_block_with_qc(
+            inserter.create_qc_for_block(&a1, None),
+            a1.timestamp_usecs() + timestamp_offset,
+            2,
+            Payload::empty(false, true),
+            vec![],
+        );
+        equivocating_ids.push(block_store.insert_block(block).await.unwrap().id());
+    }
+    equivocating_ids.sort();
+    assert_eq!(block_store.equivocations_at(2), vec![(
+        equivocating_ids[0],
+        equivocating_ids[1]
+    )]);
+    assert!(block_store.equivocations_at(1).is_empty());
+    assert!(block_store.equivocations_at(3).is_empty());
+}
+
+#[tokio::test]
+async fn test_get_blocks() {
+    let (blocks, block_store) = build_simple_tree().await;
+    let missing_id = HashValue::random();
+    assert_eq!(
+        block_store.get_blocks(&[blocks[3].id(), missing_id, blocks[0].id(), blocks[3].id()]),
+        vec![
+            Some(blocks[3].clone()),
+            None,
+            Some(blocks[0].clone()),
+            Some(blocks[3].clone())
+        ]
+    );
+    assert!(block_store.get_blocks(&[]).is_empty());
+}
+
+#[tokio::test]
+async fn test_highest_timeout_round() {
+    let block_store = build_empty_tree();
+    let tc = |round| {
+        Arc::new(TwoChainTimeoutCertificate::new(TwoChainTimeout::new(
+            1,
+            round,
+            certificate_for_genesis(),
+        )))
+    };
+    assert_eq!(block_store.highest_timeout_round(), 0);
+
+    block_store
+        .insert_2chain_timeout_certificate(tc(2))
+        .unwrap();
+    block_store
+        .insert_2chain_timeout_certificate(tc(5))
+        .unwrap();
+    assert_eq!(block_store.highest_timeout_round(), 5);
+
+    // Lower or equal rounds are ignored.
+    block_store
+        .insert_2chain_timeout_certificate(tc(3))
+        .unwrap();
+    block_store
+        .insert_2chain_timeout_certificate(tc(5))
+        .unwrap();
+    assert_eq!(block_store.highest_timeout_round(), 5);
+
+    // Only the certificates with a higher round are installed.
+    assert_eq!(block_store.inner.read().num_timeout_cert_updates(), 2);
+}
+
+#[tokio::test]
+async fn test_next_expected_round() {
+    let mut inserter = TreeInserter::default();
+    let block_store = inserter.block_store();
+    let tc = |round| {
+        Arc::new(TwoChainTimeoutCertificate::new(TwoChainTimeout::new(
+            1,
+            round,
+            certificate_for_genesis(),
+        )))
+    };
+    assert_eq!(block_store.next_expected_round(), 1);
+
+    // genesis <- a1 <- a2 <- a3, so the highest certified block is a2.
+    let genesis = block_store.ordered_root();
+    let a1 = inserter
+        .insert_block_with_qc(certificate_for_genesis(), &genesis, 1)
+        .await;
+    let a2 = inserter.insert_block(&a1, 2, None).await;
+    inserter.insert_block(&a2, 3, None).await;
+    assert_eq!(block_store.highest_certified_block(), a2);
+    assert_eq!(block_store.next_expected_round(), 3);
+
+    // A timeout cert below the highest certified block does not matter.
+    block_store
+        .insert_2chain_timeout_certificate(tc(1))
+        .unwrap();
+    assert_eq!(block_store.next_expected_round(), 3);
+
+    // A timeout cert above the highest certified block does.
+    block_store
+        .insert_2chain_timeout_certificate(tc(5))
+        .unwrap();
+    assert_eq!(block_store.next_expected_round(), 6);
+}
+
+#[tokio::test]
+async fn test_blocks_missing_qc() {
+    let mut inserter = TreeInserter::default();
+    let block_store = inserter.block_store();
+    assert!(block_store.blocks_missing_qc().is_empty());
+
+    // genesis <- a1 <- a2, inserting a2 also inserts the QC for a1.
+    let genesis = block_store.ordered_root();
+    let a1 = inserter
+        .insert_block_with_qc(certificate_for_genesis(), &genesis, 1)
+        .await;
+    assert_eq!(block_store.blocks_missing_qc(), vec![a1.id()]);
+    let a2 = inserter.insert_block(&a1, 2, None).await;
+    assert_eq!(block_store.blocks_missing_qc(), vec![a2.id()]);
+
+    block_store
+        .insert_single_quorum_cert(inserter.create_qc_for_block(&a2, None))
+        .unwrap();
+    assert!(block_store.blocks_missing_qc().is_empty());
+}
+
+#[tokio::test]
+async fn test_back_pressure_history() {
+    let (blocks, block_store) = build_simple_tree().await;
+    assert!(block_store.recent_back_pressure().is_empty());
+
+    block_store.vote_back_pressure();
+    // Ordered root a3, commit root genesis.
+    block_store.set_roots_for_test(blocks[3].id(), blocks[0].id());
+    block_store.vote_back_pressure();
+    let history = block_store.recent_back_pressure();
+    assert_eq!(
+        history
+            .iter()
+            .map(|sample| sample.pending_rounds)
+            .collect::<Vec<_>>(),
+        vec![0, 3]
+    );
+    assert!(history[0].timestamp <= history[1].timestamp);
+
+    // Ordered root a3, commit root a1.
+    block_store.set_roots_for_test(blocks[3].id(), blocks[1].id());
+    for _ in 0..BACK_PRESSURE_HISTORY_LEN {
+        block_store.vote_back_pressure();
+    }
+    let history = block_store.recent_back_pressure();
+    assert_eq!(history.len(), BACK_PRESSURE_HISTORY_LEN);
+    assert!(history.iter().all(|sample| sample.pending_rounds == 2));
+}
+
+#[tokio::test]
+async fn test_back_pressure_override() {
+    let (block_store, mut inserter) =
+        build_empty_tree_with(|builder| builder.vote_back_pressure_limit(1)).await;
+
+    // genesis <- a1 <- a2
+    let genesis = block_store.ordered_root();
+    let a1 = inserter
+        .insert_block_with_qc(certificate_for_genesis(), &genesis, 1)
+        .await;
+    let a2 = inserter.insert_block(&a1, 2, None).await;
+    assert!(!block_store.vote_back_pressure());
+
+    // Forcing back pressure on takes effect although nothing is pending.
+    block_store.set_back_pressure_for_test(true);
+    assert!(block_store.vote_back_pressure());
+    block_store.clear_back_pressure_for_test();
+    assert!(!block_store.vote_back_pressure());
+
+    // Ordered root a2 is 2 rounds ahead of the commit root, which exceeds the limit.
+    block_store.set_roots_for_test(a2.id(), genesis.id());
+    assert!(block_store.vote_back_pressure());
+
+    // Forcing back pressure off takes effect although the limit is exceeded.
+    block_store.set_back_pressure_for_test(false);
+    assert!(!block_store.vote_back_pressure());
+    block_store.clear_back_pressure_for_test();
+    assert!(block_store.vote_back_pressure());
+}
+
+#[tokio::test]
+async fn test_set_order_vote_enabled() {
+    let mut inserter = TreeInserter::default();
+    let block_store = inserter.block_store();
+    assert!(!block_store.order_vote_enabled());
+
+    // The commit root is the genesis block of the epoch, so the flag can be switched.
+    let genesis = block_store.ordered_root();
+    block_store.set_order_vote_enabled(true).unwrap();
+    assert!(block_store.order_vote_enabled());
+
+    let a1 = inserter
+        .insert_block_with_qc(certificate_for_genesis(), &genesis, 1)
+        .await;
+
+    // Blocks are ordered beyond the commit root.
+    block_store.set_roots_for_test(a1.id(), genesis.id());
+    assert!(block_store.set_order_vote_enabled(false).is_err());
+
+    // a1 neither starts nor ends the epoch.
+    block_store.set_roots_for_test(a1.id(), a1.id());
+    assert!(block_store.set_order_vote_enabled(false).is_err());
+    assert!(block_store.order_vote_enabled());
+}
+
+#[tokio::test]
+async fn test_order_vote_switch_certificate_handling() {
+    let (block_store, mut inserter) = build_empty_tree_with(|builder| {
+        builder.execution_client(mock_execution_client(
+            FinalizeOrderBehavior::CommitImmediately,
+        ))
+    })
+    .await;
+
+    // genesis <- a1 <- a2, with an ordered cert for a1 aggregated from order votes.
+    let genesis = block_store.ordered_root();
+    let a1 = inserter
+        .insert_block_with_qc(certificate_for_genesis(), &genesis, 1)
+        .await;
+    let a2 = inserter.insert_block(&a1, 2, None).await;
+    let ordered_cert = WrappedLedgerInfo::new(
+        VoteData::dummy(),
+        inserter
+            .create_qc_for_block(&a2, Some(a1.block_info()))
+            .ledger_info()
+            .clone(),
+    );
+
+    // Without order votes, the highest ordered cert of the sync info is handled as a quorum cert,
+    // which an ordered cert from order votes can't be converted to.
+    let sync_info = block_store.sync_info();
+    assert!(sync_info
+        .highest_ordered_cert()
+        .into_quorum_cert(block_store.order_vote_enabled())
+        .is_ok());
+    assert!(ordered_cert
+        .clone()
+        .into_quorum_cert(block_store.order_vote_enabled())
+        .is_err());
+
+    // With order votes, the ordered cert is inserted as is, and shows up in the sync info.
+    block_store.set_order_vote_enabled(true).unwrap();
+    block_store
+        .insert_ordered_cert(&ordered_cert)
+        .await
+        .unwrap();
+    assert_eq!(block_store.ordered_root(), a1);
+    assert_eq!(block_store.commit_root(), a1);
+    let sync_info = block_store.sync_info();
+    assert_eq!(sync_info.highest_ordered_cert().commit_info().id(), a1.id());
+    assert!(sync_info
+        .highest_ordered_cert()
+        .into_quorum_cert(block_store.order_vote_enabled())
+        .is_err());
+
+    // a1 is ordered, so the switch can't be reverted.
+    assert!(block_store.set_order_vote_enabled(false).is_err());
+}
+
+#[tokio::test]
+async fn test_estimated_memory_bytes() {
+    let mut inserter = TreeInserter::default();
+    let block_store = inserter.block_store();
+    let genesis = block_store.ordered_root();
+    let initial_estimate = block_store.estimated_memory_bytes();
+
+    let a1 = inserter
+        .insert_block_with_qc(certificate_for_genesis(), &genesis, 1)
+        .await;
+    let estimate_with_empty_block = block_store.estimated_memory_bytes();
+    assert!(estimate_with_empty_block > initial_estimate);
+
+    let payload = Payload::DirectMempool(create_vec_signed_transactions(10));
+    let payload_size = payload.size();
+    let a2 = inserter.create_block_with_qc(
+        inserter.create_qc_for_block(&a1, None),
+        a1.timestamp_usecs() + 1,
+        2,
+        payload,
+        vec![],
+    );
+    block_store.insert_block_with_qc(a2).await.unwrap();
+    assert!(block_store.estimated_memory_bytes() >= estimate_with_empty_block + payload_size);
+}
+
+#[tokio::test]
+async fn test_storage_write_observer() {
+    let (blocks, block_store) = build_simple_tree().await;
+    let snapshot = block_store.serialize_tree();
+
+    let storage_write_observer = Arc::new(RecordingStorageWriteObserver::default());
+    let restored =
+        restore_empty_tree_from_snapshot(&snapshot, Some(storage_write_observer.clone()))
+            .await
+            .unwrap();
+    // The restored blocks and quorum certs are not written to storage again.
+    assert!(storage_write_observer.writes.lock().is_empty());
+
+    // Inserting a3's child saves the quorum cert for a3 first, then the block itself.
+    let mut inserter =
+        TreeInserter::new_with_store(ValidatorSigner::random(None), Arc::new(restored));
+    let a4 = inserter.insert_block(&blocks[3], 7, None).await;
+    assert_eq!(*storage_write_observer.writes.lock(), vec![
+        (vec![], vec![blocks[3].id()]),
+        (vec![a4.id()], vec![]),
+    ]);
+}
+
+#[tokio::test]
+async fn test_block_store_builder() {
+    let (initial_data, storage) = EmptyStorage::start_for_testing();
+    let block_store = BlockStore::builder()
+        .storage(storage)
+        .execution_client(Arc::new(DummyExecutionClient))
+        .time_service(Arc::new(SimulatedTimeService::new()))
+        .payload_manager(Arc::new(DirectMempoolPayloadManager::new()))
+        .build(initial_data)
+        .await
+        .unwrap();
+    assert_eq!(block_store.ordered_root(), block_store.commit_root());
+    assert_eq!(
+        block_store.vote_back_pressure_limit,
+        ConsensusConfig::default().vote_back_pressure_limit
+    );
+    assert!(!block_store.order_vote_enabled);
+
+    // The required fields must be set.
+    let (initial_data, storage) = EmptyStorage::start_for_testing();
+    let error = BlockStore::builder()
+        .storage(storage)
+        .time_service(Arc::new(SimulatedTimeService::new()))
+        .payload_manager(Arc::new(DirectMempoolPayloadManager::new()))
+        .build(initial_data)
+        .await
+        .unwrap_err();
+    assert!(error.to_string().contains("execution client is not set"));
+}
+
+#[tokio::test]
+async fn test_deterministic_insert_of_future_block() {
+    let clock = Arc::new(ManualClock::default());
+    let (initial_data, storage) = EmptyStorage::start_for_testing();
+    let block_store = Arc::new(BlockStore::new_deterministic(
+        storage,
+        initial_data,
+        Arc::new(DummyExecutionClient),
+        Arc::new(DirectMempoolPayloadManager::new()),
+        clock.clone(),
+    ));
+    let inserter = TreeInserter::new_with_store(ValidatorSigner::random(None), block_store.clone());
+
+    // A block an hour ahead of the logical clock is inserted without waiting for real time.
+    let block_time = Duration::from_secs(3600);
+    let block = inserter.create_block_with_qc(
+        certificate_for_genesis(),
+        block_time.as_micros() as u64,
+        1,
+        Payload::empty(false, true),
+        vec![],
+    );
+    let inserted_block = block_store.insert_block(block).await.unwrap();
+    assert_eq!(inserted_block.round(), 1);
+    assert!(clock.now() > block_time);
+}
+
+#[tokio::test]
+async fn test_replay_inserts() {
+    use futures::{FutureExt, StreamExt};
+
+    let clock = Arc::new(ManualClock::default());
+    let (initial_data, storage) = EmptyStorage::start_for_testing();
+    let block_store = BlockStore::new_deterministic(
+        storage,
+        initial_data,
+        Arc::new(DummyExecutionClient),
+        Arc::new(DirectMempoolPayloadManager::new()),
+        clock.clone(),
+    );
+    let signer = ValidatorSigner::random(None);
+    let proposal = |round, block_time: Duration| {
+        Block::new_proposal(
+            Payload::empty(false, true),
+            round,
+            block_time.as_micros() as u64,
+            certificate_for_genesis(),
+            &signer,
+            vec![],
+        )
+        .unwrap()
+    };
+
+    // a1 is inserted half an hour before its timestamp, b2 an hour after its timestamp.
+    let (a1, b2) = (
+        proposal(1, Duration::from_secs(3600)),
+        proposal(2, Duration::from_secs(7200)),
+    );
+    let mut schedule = BlockInsertSchedule::default();
+    schedule.record(Duration::from_secs(1800), a1.clone());
+    schedule.record(Duration::from_secs(10800), b2.clone());
+
+    // A timeout scheduled in between fires during the replay, without waiting for real time.
+    let (tx, mut rx) = aptos_channels::new_test(1);
+    block_store
+        .time_service
+        .run_after(Duration::from_secs(9000), SendTask::make(tx, ()));
+    assert!(rx.next().now_or_never().is_none());
+
+    let inserted = block_store.replay_inserts(&schedule).await.unwrap();
+    assert_eq!(
+        inserted.iter().map(|block| block.id()).collect::<Vec<_>>(),
+        vec![a1.id(), b2.id()]
+    );
+    assert_eq!(clock.now(), Duration::from_secs(10800));
+    assert_eq!(rx.next().now_or_never(), Some(Some(())));
+}
+
+#[tokio::test]
+async fn test_tree_as_json() {
+    let (blocks, block_store) = build_simple_tree().await;
+    let tree = block_store.tree_as_json();
+    let edge = |from: &Arc<PipelinedBlock>, to: &Arc<PipelinedBlock>| serde_json::json!({"from": from.id().to_hex(), "to": to.id().to_hex()});
+
+    assert_eq!(tree["root"], blocks[0].id().to_hex());
+    let node_ids: Vec<_> = tree["nodes"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|node| node["id"].as_str().unwrap().to_string())
+        .collect();
+    assert_eq!(
+        node_ids,
+        blocks
+            .iter()
+            .map(|block| block.id().to_hex())
+            .collect::<Vec<_>>()
+    );
+    assert_eq!(tree["nodes"][6]["round"], 6);
+    assert!(tree["nodes"][6].get("payload").is_none());
+
+    // In the simple tree, each block carries the QC of its parent.
+    let parent_edges = tree["parent_edges"].as_array().unwrap();
+    let qc_edges = tree["qc_edges"].as_array().unwrap();
+    assert_eq!(parent_edges.len(), 6);
+    assert_eq!(parent_edges, qc_edges);
+    assert!(parent_edges.contains(&edge(&blocks[1], &blocks[0])));
+    assert!(parent_edges.contains(&edge(&blocks[4], &blocks[0])));
+    assert!(parent_edges.contains(&edge(&blocks[6], &blocks[4])));
+}
+
+#[tokio::test]
+async fn test_illegal_timestamp() {
+    let signer = ValidatorSigner::random(None);
+    let block_store = build_empty_tree();
+    let genesis = block_store.ordered_root();
+    let block_with_illegal_timestamp = Block::new_proposal(
+        Payload::empty(false, true),
+        0,
+        // This timestamp is illegal, it is the same as genesis
+        genesis.timestamp_usecs(),
+        certificate_for_genesis(),
+        &signer,
+        Vec::new(),
+    )
+    .unwrap();
+    let result = block_store.insert_block(block_with_illegal_timestamp).await;
+    assert!(result.is_err());
+}
+
+#[tokio::test]
+async fn test_timestamp_monotonicity() {
+    let (block_store, mut inserter) =
+        build_empty_tree_with(|builder| builder.enforce_timestamp_monotonicity(true)).await;
+    let genesis = block_store.ordered_root();
+    let a1 = inserter
+        .insert_block_with_qc(certificate_for_genesis(), &genesis, 1)
+        .await;
+    let a1_qc = inserter.create_qc_for_block(&a1, None);
+
+    // The same timestamp as the parent is rejected for a regular proposal.
+    let same_timestamp = inserter.create_block_with_qc(
+        a1_qc.clone(),
+        a1.timestamp_usecs(),
+        2,
+        Payload::empty(false, true),
+        vec![],
+    );
+    assert!(block_store.insert_block(same_timestamp).await.is_err());
+
+    // An earlier timestamp than the parent is rejected as well.
+    let earlier_timestamp = inserter.create_block_with_qc(
+        a1_qc.clone(),
+        a1.timestamp_usecs() - 1,
+        2,
+        Payload::empty(false, true),
+        vec![],
+    );
+    assert!(block_store.insert_block(earlier_timestamp).await.is_err());
+
+    // NIL blocks inherit the timestamp of their parent.
+    let nil_block = Block::new_nil(2, a1_qc.clone(), vec![]);
+    assert_eq!(nil_block.timestamp_usecs(), a1.timestamp_usecs());
+    assert!(block_store.insert_block(nil_block).await.is_ok());
+
+    let later_timestamp = inserter.create_block_with_qc(
+        a1_qc,
+        a1.timestamp_usecs() + 1,
+        3,
+        Payload::empty(false, true),
+        vec![],
+    );
+    assert!(block_store.insert_block(later_timestamp).await.is_ok());
+}
+
+#[tokio::test]
+async fn test_timestamp_monotonicity_disabled() {
+    let mut inserter = TreeInserter::default();
+    let block_store = inserter.block_store();
+    let genesis = block_store.ordered_root();
+    let a1 = inserter
+        .insert_block_with_qc(certificate_for_genesis(), &genesis, 1)
+        .await;
+
+    // Without the check, the block store relies on upstream validation of timestamps.
+    let same_timestamp = inserter.create_block_with_qc(
+        inserter.create_qc_for_block(&a1, None),
+        a1.timestamp_usecs(),
+        2,
+        Payload::empty(false, true),
+        vec![],
+    );
+    assert!(block_store.insert_block(same_timestamp).await.is_ok());
+}
+
+#[tokio::test]
+async fn test_highest_block_with_available_payload() {
+    let payload_manager = Arc::new(PartiallyAvailablePayloadManager {
+        available_rounds: [1, 2].into_iter().collect(),
+    });
+    let (block_store, mut inserter) =
+        build_empty_tree_with(|builder| builder.payload_manager(payload_manager)).await;
+    assert_eq!(block_store.highest_block_with_available_payload(), None);
+
+    // genesis <- a1 <- a2 <- a3, with only the payloads of a1 and a2 available
+    let genesis = block_store.ordered_root();
+    let a1 = inserter
+        .insert_block_with_qc(certificate_for_genesis(), &genesis, 1)
+        .await;
+    assert_eq!(block_store.highest_block_with_available_payload(), Some(1));
+    let a2 = inserter.insert_block(&a1, 2, None).await;
+    inserter.insert_block(&a2, 3, None).await;
+    assert_eq!(block_store.highest_block_with_available_payload(), Some(2));
 }
 
 #[tokio::test]
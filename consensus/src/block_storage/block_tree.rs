@@ -3,6 +3,7 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use crate::{
+    block_storage::{CommitProofChain, PrunedBlockRetention},
     counters,
     counters::update_counters_for_committed_blocks,
     logging::{LogEvent, LogSchema},
@@ -10,7 +11,7 @@ use crate::{
 };
 use anyhow::bail;
 use aptos_consensus_types::{
-    pipelined_block::PipelinedBlock, quorum_cert::QuorumCert,
+    common::Author, pipelined_block::PipelinedBlock, quorum_cert::QuorumCert,
     timeout_2chain::TwoChainTimeoutCertificate, wrapped_ledger_info::WrappedLedgerInfo,
 };
 use aptos_crypto::HashValue;
@@ -20,8 +21,10 @@ use aptos_types::{
     ledger_info::LedgerInfoWithSignatures,
 };
 use mirai_annotations::precondition;
+use serde_json::json;
 use std::{
-    collections::{vec_deque::VecDeque, BTreeMap, HashMap, HashSet},
+    collections::{hash_map::Entry, vec_deque::VecDeque, BTreeMap, HashMap, HashSet},
+    mem::size_of,
     sync::Arc,
 };
 
@@ -57,8 +60,22 @@ impl LinkableBlock {
             child_id,
         );
     }
+
+    /// Rough estimate of the memory used by the block, its payload and its children links.
+    fn estimated_memory_bytes(&self) -> usize {
+        size_of::<LinkableBlock>()
+            + size_of::<PipelinedBlock>()
+            + self.children.len() * size_of::<HashValue>()
+            + self
+                .executed_block
+                .payload()
+                .map_or(0, |payload| payload.size())
+    }
 }
 
+/// Rough estimate of the memory used by an entry of `BlockTree::id_to_quorum_cert`.
+const QUORUM_CERT_ENTRY_BYTES: usize = size_of::<HashValue>() + size_of::<QuorumCert>();
+
 impl LinkableBlock {
     pub fn id(&self) -> HashValue {
         self.executed_block().id()
@@ -82,6 +99,8 @@ pub struct BlockTree {
     highest_quorum_cert: Arc<QuorumCert>,
     /// The highest 2-chain timeout certificate (if any).
     highest_2chain_timeout_cert: Option<Arc<TwoChainTimeoutCertificate>>,
+    /// Number of times the highest 2-chain timeout certificate was replaced in this tree.
+    num_timeout_cert_updates: u64,
     /// The quorum certificate that has highest commit info.
     highest_ordered_cert: Arc<WrappedLedgerInfo>,
     /// The quorum certificate that has highest commit decision info.
@@ -92,9 +111,13 @@ pub struct BlockTree {
     pruned_block_ids: VecDeque<HashValue>,
     /// Num pruned blocks to keep in memory.
     max_pruned_blocks_in_mem: usize,
+    /// Chooses which pruned blocks are kept in memory, the most recently pruned ones if not set.
+    pruned_block_retention: Option<Arc<dyn PrunedBlockRetention>>,
 
     /// Round to Block index. We expect only one block per round.
     round_to_ids: BTreeMap<Round, HashValue>,
+    /// See `estimated_memory_bytes`, maintained as blocks and quorum certs are added and removed.
+    estimated_memory_bytes: usize,
 }
 
 impl BlockTree {
@@ -105,6 +128,7 @@ impl BlockTree {
         root_commit_cert: WrappedLedgerInfo,
         max_pruned_blocks_in_mem: usize,
         highest_2chain_timeout_cert: Option<Arc<TwoChainTimeoutCertificate>>,
+        pruned_block_retention: Option<Arc<dyn PrunedBlockRetention>>,
     ) -> Self {
         assert_eq!(
             root.id(),
@@ -128,7 +152,7 @@ impl BlockTree {
 
         let pruned_block_ids = VecDeque::with_capacity(max_pruned_blocks_in_mem);
 
-        BlockTree {
+        let mut tree = BlockTree {
             id_to_block,
             ordered_root_id: root_id,
             commit_root_id: root_id, // initially we set commit_root_id = root_id
@@ -139,9 +163,22 @@ impl BlockTree {
             id_to_quorum_cert,
             pruned_block_ids,
             max_pruned_blocks_in_mem,
+            pruned_block_retention,
             highest_2chain_timeout_cert,
+            num_timeout_cert_updates: 0,
             round_to_ids,
-        }
+            estimated_memory_bytes: 0,
+        };
+        tree.set_estimated_memory_bytes(
+            tree.linkable_root().estimated_memory_bytes() + QUORUM_CERT_ENTRY_BYTES,
+        );
+        tree.update_oldest_uncommitted_round_gauge();
+        tree
+    }
+
+    fn set_estimated_memory_bytes(&mut self, estimated_memory_bytes: usize) {
+        self.estimated_memory_bytes = estimated_memory_bytes;
+        counters::BLOCK_TREE_ESTIMATED_MEMORY_BYTES.set(estimated_memory_bytes as i64);
     }
 
     // This method will only be used in this module.
@@ -172,13 +209,20 @@ impl BlockTree {
             .expect("Root must exist")
     }
 
-    fn remove_block(&mut self, block_id: HashValue) {
-        // Remove the block from the store
-        if let Some(block) = self.id_to_block.remove(&block_id) {
+    /// Removes the block from the store, returning it if it was there.
+    fn remove_block(&mut self, block_id: HashValue) -> Option<Arc<PipelinedBlock>> {
+        let mut estimated_memory_bytes = self.estimated_memory_bytes;
+        let removed_block = self.id_to_block.remove(&block_id).map(|block| {
+            estimated_memory_bytes -= block.estimated_memory_bytes();
             let round = block.executed_block().round();
             self.round_to_ids.remove(&round);
-        };
-        self.id_to_quorum_cert.remove(&block_id);
+            block.executed_block().clone()
+        });
+        if self.id_to_quorum_cert.remove(&block_id).is_some() {
+            estimated_memory_bytes -= QUORUM_CERT_ENTRY_BYTES;
+        }
+        self.set_estimated_memory_bytes(estimated_memory_bytes);
+        removed_block
     }
 
     pub(super) fn block_exists(&self, block_id: &HashValue) -> bool {
@@ -222,6 +266,21 @@ impl BlockTree {
     /// Replace highest timeout cert with the given value.
     pub(super) fn replace_2chain_timeout_cert(&mut self, tc: Arc<TwoChainTimeoutCertificate>) {
         self.highest_2chain_timeout_cert.replace(tc);
+        self.num_timeout_cert_updates += 1;
+    }
+
+    pub(super) fn highest_timeout_round(&self) -> Round {
+        self.highest_2chain_timeout_cert
+            .as_ref()
+            .map_or(0, |tc| tc.round())
+    }
+
+    /// The round following both the highest certified block and the highest timeout cert.
+    pub(super) fn next_expected_round(&self) -> Round {
+        self.highest_certified_block()
+            .round()
+            .max(self.highest_timeout_round())
+            + 1
     }
 
     pub(super) fn highest_ordered_cert(&self) -> Arc<WrappedLedgerInfo> {
@@ -256,6 +315,12 @@ impl BlockTree {
                 None => bail!("Parent block {} not found", block.parent_id()),
             };
             let linkable_block = LinkableBlock::new(block);
+            // The new block, and the link to it from its parent.
+            self.set_estimated_memory_bytes(
+                self.estimated_memory_bytes
+                    + linkable_block.estimated_memory_bytes()
+                    + size_of::<HashValue>(),
+            );
             let arc_block = Arc::clone(linkable_block.executed_block());
             assert!(self.id_to_block.insert(block_id, linkable_block).is_none());
             // Note: the assumption is that we have/enforce unequivocal proposer election.
@@ -307,9 +372,10 @@ impl BlockTree {
             None => bail!("Block {} not found", block_id),
         }
 
-        self.id_to_quorum_cert
-            .entry(block_id)
-            .or_insert_with(|| Arc::clone(&qc));
+        if let Entry::Vacant(entry) = self.id_to_quorum_cert.entry(block_id) {
+            entry.insert(Arc::clone(&qc));
+            self.set_estimated_memory_bytes(self.estimated_memory_bytes + QUORUM_CERT_ENTRY_BYTES);
+        }
 
         if self.highest_ordered_cert.commit_info().round() < qc.commit_info().round() {
             // Question: We are updating highest_ordered_cert but not highest_ordered_root. Is that fine?
@@ -366,32 +432,78 @@ impl BlockTree {
     pub(super) fn update_ordered_root(&mut self, root_id: HashValue) {
         assert!(self.block_exists(&root_id));
         self.ordered_root_id = root_id;
+        self.update_oldest_uncommitted_round_gauge();
     }
 
     pub(super) fn update_commit_root(&mut self, root_id: HashValue) {
         assert!(self.block_exists(&root_id));
         self.commit_root_id = root_id;
+        self.update_oldest_uncommitted_round_gauge();
+    }
+
+    /// See `BlockReader::oldest_uncommitted_round`, also reported in the `OLDEST_UNCOMMITTED_ROUND`
+    /// gauge whenever one of the roots moves.
+    pub(super) fn oldest_uncommitted_round(&self) -> Option<Round> {
+        self.path_from_commit_root(self.ordered_root_id)
+            .unwrap_or_default()
+            .iter()
+            .map(|block| block.round())
+            .min()
+    }
+
+    fn update_oldest_uncommitted_round_gauge(&self) {
+        counters::OLDEST_UNCOMMITTED_ROUND.set(self.oldest_uncommitted_round().unwrap_or(0) as i64);
     }
 
     /// Process the data returned by the prune_tree, they're separated because caller might
     /// be interested in doing extra work e.g. delete from persistent storage.
     /// Note that we do not necessarily remove the pruned blocks: they're kept in a separate buffer
     /// for some time in order to enable other peers to retrieve the blocks even after they've
-    /// been committed.
-    pub(super) fn process_pruned_blocks(&mut self, mut newly_pruned_blocks: VecDeque<HashValue>) {
+    /// been committed. Returns the blocks removed from memory, e.g. to be archived once the tree
+    /// lock is released.
+    pub(super) fn process_pruned_blocks(
+        &mut self,
+        mut newly_pruned_blocks: VecDeque<HashValue>,
+    ) -> Vec<Arc<PipelinedBlock>> {
         counters::NUM_BLOCKS_IN_TREE.sub(newly_pruned_blocks.len() as i64);
         // The newly pruned blocks are pushed back to the deque pruned_block_ids.
         // In case the overall number of the elements is greater than the predefined threshold,
-        // the oldest elements (in the front of the deque) are removed from the tree.
+        // the oldest elements (in the front of the deque) are removed from the tree, unless the
+        // pruned block retention chooses which ones to keep.
         self.pruned_block_ids.append(&mut newly_pruned_blocks);
-        if self.pruned_block_ids.len() > self.max_pruned_blocks_in_mem {
+        let mut removed_blocks = vec![];
+        if self.pruned_block_ids.len() <= self.max_pruned_blocks_in_mem {
+            return removed_blocks;
+        }
+        if let Some(pruned_block_retention) = self.pruned_block_retention.clone() {
+            let pruned_blocks: Vec<_> = self
+                .pruned_block_ids
+                .iter()
+                .filter_map(|id| self.get_block(id))
+                .collect();
+            let retained = pruned_block_retention
+                .select_retained(&pruned_blocks, self.max_pruned_blocks_in_mem);
+            // Walk from the most recently pruned block, so that the most recent of the selected
+            // blocks are kept if the selection exceeds the budget.
+            let mut pruned_block_ids = std::mem::take(&mut self.pruned_block_ids);
+            while let Some(id) = pruned_block_ids.pop_back() {
+                if self.pruned_block_ids.len() < self.max_pruned_blocks_in_mem
+                    && retained.contains(&id)
+                {
+                    self.pruned_block_ids.push_front(id);
+                } else {
+                    removed_blocks.extend(self.remove_block(id));
+                }
+            }
+        } else {
             let num_blocks_to_remove = self.pruned_block_ids.len() - self.max_pruned_blocks_in_mem;
             for _ in 0..num_blocks_to_remove {
                 if let Some(id) = self.pruned_block_ids.pop_front() {
-                    self.remove_block(id);
+                    removed_blocks.extend(self.remove_block(id));
                 }
             }
         }
+        removed_blocks
     }
 
     /// Returns all the blocks between the commit root and the given block, including the given block
@@ -444,18 +556,241 @@ impl BlockTree {
         self.path_from_root_to_block(block_id, self.commit_root_id, self.commit_root().round())
     }
 
+    /// Returns all the blocks between the ancestor and the descendant, including the descendant
+    /// but excluding the ancestor. Returns None if the ancestor is not in the tree or is not an
+    /// ancestor of the descendant.
+    pub(super) fn path_between(
+        &self,
+        ancestor_id: HashValue,
+        descendant_id: HashValue,
+    ) -> Option<Vec<Arc<PipelinedBlock>>> {
+        let ancestor_round = self.get_block(&ancestor_id)?.round();
+        self.path_from_root_to_block(descendant_id, ancestor_id, ancestor_round)
+    }
+
+    /// Returns the certs justifying the commit of the given block, or None if the block is not
+    /// covered by the QC chains of the certs in memory, i.e. if it is not committed or has already
+    /// been pruned. The commit cert is the one picked by `committed_ledger_info`.
+    pub(super) fn commit_proof_for(&self, block_id: HashValue) -> Option<CommitProofChain> {
+        let commit_cert = self.commit_cert_for(block_id)?;
+        let ordered_cert = self.highest_ordered_cert();
+        if !self.qc_chain_reaches(ordered_cert.commit_info().id(), block_id) {
+            return None;
+        }
+        Some(CommitProofChain {
+            quorum_cert: self.get_quorum_cert_for_block(&block_id)?,
+            ordered_cert,
+            commit_cert,
+        })
+    }
+
+    /// Returns the signed ledger info committing the given block, or None if the block is not
+    /// committed or has already been pruned from memory.
+    pub(super) fn committed_ledger_info(
+        &self,
+        block_id: HashValue,
+    ) -> Option<LedgerInfoWithSignatures> {
+        self.commit_cert_for(block_id)
+            .map(|commit_cert| commit_cert.ledger_info().clone())
+    }
+
+    /// Returns the cert committing the given block. The highest commit cert commits the commit
+    /// root together with all its ancestors, but a cert committing exactly the given block is
+    /// preferred while it is still in memory. Returns None if the QC chain of the highest commit
+    /// cert doesn't reach the block.
+    fn commit_cert_for(&self, block_id: HashValue) -> Option<Arc<WrappedLedgerInfo>> {
+        let commit_cert = self.highest_commit_cert();
+        if !self.qc_chain_reaches(commit_cert.commit_info().id(), block_id) {
+            return None;
+        }
+        if commit_cert.commit_info().id() != block_id {
+            if let Some(qc) = self
+                .id_to_quorum_cert
+                .values()
+                .find(|qc| qc.commit_info().id() == block_id)
+            {
+                return Some(Arc::new(qc.into_wrapped_ledger_info()));
+            }
+        }
+        Some(commit_cert)
+    }
+
+    /// Returns whether following the QC links from `from_id` leads to `block_id`, with all the
+    /// blocks on the way still in memory.
+    fn qc_chain_reaches(&self, from_id: HashValue, block_id: HashValue) -> bool {
+        let target_round = match self.get_block(&block_id) {
+            Some(block) => block.round(),
+            None => return false,
+        };
+        let mut current_id = from_id;
+        while current_id != block_id {
+            match self.get_block(&current_id) {
+                Some(block) if block.round() > target_round => {
+                    current_id = block.quorum_cert().certified_block().id();
+                },
+                _ => return false,
+            }
+        }
+        true
+    }
+
+    /// Returns the pairs of distinct blocks in the tree proposed by the same author at the given
+    /// round, each pair ordered by block id.
+    pub(super) fn equivocations_at(&self, round: Round) -> Vec<(HashValue, HashValue)> {
+        let mut blocks_by_author: BTreeMap<Author, Vec<HashValue>> = BTreeMap::new();
+        for linkable_block in self.id_to_block.values() {
+            let block = linkable_block.executed_block();
+            if block.round() != round {
+                continue;
+            }
+            if let Some(author) = block.block().author() {
+                blocks_by_author.entry(author).or_default().push(block.id());
+            }
+        }
+
+        let mut equivocations = vec![];
+        for mut block_ids in blocks_by_author.into_values() {
+            block_ids.sort();
+            for (i, first_id) in block_ids.iter().enumerate() {
+                for second_id in &block_ids[i + 1..] {
+                    equivocations.push((*first_id, *second_id));
+                }
+            }
+        }
+        equivocations
+    }
+
+    /// Returns the id of the block certified by the QC carried by the given block, which differs
+    /// from the parent id for DAG blocks.
+    pub(super) fn qc_parent(&self, block_id: &HashValue) -> Option<HashValue> {
+        self.get_block(block_id)
+            .map(|block| block.quorum_cert().certified_block().id())
+    }
+
+    /// Returns the ids of the blocks on the path of QC links from the given block back to the
+    /// commit root (both included), or None if the QC links do not lead to the commit root.
+    pub(super) fn qc_path_to_root(&self, block_id: HashValue) -> Option<Vec<HashValue>> {
+        let commit_root_round = self.commit_root().round();
+        let mut path = vec![block_id];
+        let mut current_id = block_id;
+        while current_id != self.commit_root_id {
+            let block = self.get_block(&current_id)?;
+            if block.round() <= commit_root_round {
+                return None;
+            }
+            current_id = block.quorum_cert().certified_block().id();
+            path.push(current_id);
+        }
+        Some(path)
+    }
+
+    /// Returns the committed blocks with a round greater than the given one that are still kept in
+    /// memory, ordered by round, and whether some of them were omitted because they have already
+    /// been pruned from memory.
+    pub(super) fn committed_blocks_since(&self, round: Round) -> (Vec<Arc<PipelinedBlock>>, bool) {
+        let mut blocks = vec![];
+        let mut truncated = false;
+        let mut next_block = Some(self.commit_root());
+        while let Some(block) = next_block {
+            if block.round() <= round {
+                break;
+            }
+            next_block = self.get_block(&block.parent_id());
+            truncated =
+                next_block.is_none() && block.quorum_cert().certified_block().round() > round;
+            blocks.push(block);
+        }
+        blocks.reverse();
+        (blocks, truncated)
+    }
+
+    /// Returns the ids of the blocks in the tree for which no QC is known yet, ordered by id.
+    pub(super) fn blocks_missing_qc(&self) -> Vec<HashValue> {
+        let mut block_ids: Vec<HashValue> = self
+            .id_to_block
+            .keys()
+            .filter(|block_id| !self.id_to_quorum_cert.contains_key(*block_id))
+            .copied()
+            .collect();
+        block_ids.sort();
+        block_ids
+    }
+
+    /// Returns all the descendants of the commit root (excluding the root itself), ordered by
+    /// round. Pruned blocks that are still kept in memory are not included.
+    pub(super) fn uncommitted_blocks(&self) -> Vec<Arc<PipelinedBlock>> {
+        let mut blocks = vec![];
+        let mut to_visit = vec![self.linkable_root()];
+        while let Some(block) = to_visit.pop() {
+            for child_id in block.children() {
+                let child = self
+                    .get_linkable_block(child_id)
+                    .expect("Child must exist in the tree");
+                blocks.push(child.executed_block().clone());
+                to_visit.push(child);
+            }
+        }
+        blocks.sort_by_key(|block| block.round());
+        blocks
+    }
+
+    /// Returns the topology of the tree rooted at the commit root as JSON: a node per block (id,
+    /// round, epoch and timestamp only, no payloads), together with the parent links and the QC
+    /// links between them. Nodes are ordered by round.
+    pub(super) fn tree_as_json(&self) -> serde_json::Value {
+        let mut blocks = vec![self.linkable_root().executed_block().clone()];
+        blocks.extend(self.uncommitted_blocks());
+
+        let mut nodes = vec![];
+        let mut parent_edges = vec![];
+        let mut qc_edges = vec![];
+        for block in &blocks {
+            nodes.push(json!({
+                "id": block.id().to_hex(),
+                "round": block.round(),
+                "epoch": block.epoch(),
+                "timestamp_usecs": block.timestamp_usecs(),
+            }));
+            if block.id() != self.commit_root_id {
+                parent_edges.push(json!({
+                    "from": block.id().to_hex(),
+                    "to": block.parent_id().to_hex(),
+                }));
+                qc_edges.push(json!({
+                    "from": block.id().to_hex(),
+                    "to": block.quorum_cert().certified_block().id().to_hex(),
+                }));
+            }
+        }
+        json!({
+            "root": self.commit_root_id.to_hex(),
+            "nodes": nodes,
+            "parent_edges": parent_edges,
+            "qc_edges": qc_edges,
+        })
+    }
+
+    /// Returns a rough estimate of the memory used by the blocks (including the pruned blocks that
+    /// are still kept in memory), their payloads and the quorum certs stored in the tree. The
+    /// estimate is updated as blocks and quorum certs are added and removed, and reported in the
+    /// `BLOCK_TREE_ESTIMATED_MEMORY_BYTES` gauge.
+    pub(super) fn estimated_memory_bytes(&self) -> usize {
+        self.estimated_memory_bytes
+    }
+
     pub(super) fn max_pruned_blocks_in_mem(&self) -> usize {
         self.max_pruned_blocks_in_mem
     }
 
     /// Update the counters for committed blocks and prune them from the in-memory and persisted store.
+    /// Returns the blocks removed from memory, see `process_pruned_blocks`.
     pub fn commit_callback_deprecated(
         &mut self,
         storage: Arc<dyn PersistentLivenessStorage>,
         blocks_to_commit: &[Arc<PipelinedBlock>],
         finality_proof: WrappedLedgerInfo,
         commit_decision: LedgerInfoWithSignatures,
-    ) {
+    ) -> Vec<Arc<PipelinedBlock>> {
         let commit_proof = finality_proof
             .create_merged_with_executed_state(commit_decision)
             .expect("Inconsistent commit proof and evaluation decision, cannot commit block");
@@ -465,16 +800,18 @@ impl BlockTree {
         let block_id = last_block.id();
         let block_round = last_block.round();
 
-        self.commit_callback(storage, block_id, block_round, commit_proof);
+        self.commit_callback(storage, block_id, block_round, commit_proof)
     }
 
+    /// Prunes the tree up to the committed block, returning the blocks removed from memory, see
+    /// `process_pruned_blocks`.
     pub fn commit_callback(
         &mut self,
         storage: Arc<dyn PersistentLivenessStorage>,
         block_id: HashValue,
         block_round: Round,
         commit_proof: WrappedLedgerInfo,
-    ) {
+    ) -> Vec<Arc<PipelinedBlock>> {
         let current_round = self.commit_root().round();
         let committed_round = block_round;
 
@@ -491,8 +828,9 @@ impl BlockTree {
             // executor.
             warn!(error = ?e, "fail to delete block");
         }
-        self.process_pruned_blocks(ids_to_remove);
+        let removed_blocks = self.process_pruned_blocks(ids_to_remove);
         self.update_highest_commit_cert(commit_proof);
+        removed_blocks
     }
 }
 
@@ -524,4 +862,9 @@ impl BlockTree {
     pub(super) fn pruned_blocks_in_mem(&self) -> usize {
         self.pruned_block_ids.len()
     }
+
+    /// The number of times the highest timeout certificate was replaced
+    pub(super) fn num_timeout_cert_updates(&self) -> u64 {
+        self.num_timeout_cert_updates
+    }
 }
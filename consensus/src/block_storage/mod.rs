@@ -3,6 +3,8 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use aptos_consensus_types::{
+    block::Block,
+    common::Round,
     pipelined_block::{ExecutionSummary, PipelinedBlock},
     quorum_cert::QuorumCert,
     sync_info::SyncInfo,
@@ -10,11 +12,15 @@ use aptos_consensus_types::{
     wrapped_ledger_info::WrappedLedgerInfo,
 };
 use aptos_crypto::HashValue;
+use aptos_types::ledger_info::LedgerInfoWithSignatures;
+#[cfg(any(test, feature = "fuzzing"))]
+pub use block_store::BlockInsertSchedule;
 pub use block_store::{
     sync_manager::{BlockRetriever, NeedFetchResult},
-    BlockStore,
+    BackPressureSample, BlockStore, BlockStoreBuilder, BlockStoreHealth, CommitAttemptSummary,
+    InsertBlockError, InsertQcError, BACK_PRESSURE_HISTORY_LEN,
 };
-use std::{sync::Arc, time::Duration};
+use std::{collections::HashSet, sync::Arc, time::Duration};
 
 mod block_store;
 mod block_tree;
@@ -28,6 +34,10 @@ pub trait BlockReader: Send + Sync {
     /// Try to get a block with the block_id, return an Arc of it if found.
     fn get_block(&self, block_id: HashValue) -> Option<Arc<PipelinedBlock>>;
 
+    /// Get the blocks with the given ids under a single lock, returning None for each block that
+    /// is not found. The result is aligned with `block_ids`.
+    fn get_blocks(&self, block_ids: &[HashValue]) -> Vec<Option<Arc<PipelinedBlock>>>;
+
     /// Get the current ordered root block of the BlockTree.
     fn ordered_root(&self) -> Arc<PipelinedBlock>;
 
@@ -47,6 +57,15 @@ pub trait BlockReader: Send + Sync {
 
     fn path_from_commit_root(&self, block_id: HashValue) -> Option<Vec<Arc<PipelinedBlock>>>;
 
+    /// Returns all the blocks between the ancestor and the descendant, including the descendant
+    /// but excluding the ancestor, in the same way as `path_from_ordered_root`.
+    /// In case the descendant is not a successor of the ancestor, return None.
+    fn path_between(
+        &self,
+        ancestor: HashValue,
+        descendant: HashValue,
+    ) -> Option<Vec<Arc<PipelinedBlock>>>;
+
     /// Return the certified block with the highest round.
     #[cfg(test)]
     fn highest_certified_block(&self) -> Arc<PipelinedBlock>;
@@ -60,6 +79,13 @@ pub trait BlockReader: Send + Sync {
     /// Return the highest timeout certificate if available.
     fn highest_2chain_timeout_cert(&self) -> Option<Arc<TwoChainTimeoutCertificate>>;
 
+    /// Return the round of the highest 2-chain timeout certificate, or 0 if there is none.
+    fn highest_timeout_round(&self) -> Round;
+
+    /// Return the round expected for the next proposal, i.e. the round following both the highest
+    /// certified block and the highest 2-chain timeout certificate.
+    fn next_expected_round(&self) -> Round;
+
     /// Return the highest commit decision wrapped ledger info.
     fn highest_commit_cert(&self) -> Arc<WrappedLedgerInfo>;
 
@@ -73,4 +99,102 @@ pub trait BlockReader: Send + Sync {
     fn pipeline_pending_latency(&self, proposal_timestamp: Duration) -> Duration;
 
     fn get_recent_block_execution_times(&self, num_blocks: usize) -> Vec<ExecutionSummary>;
+
+    /// Return the lowest round that is ordered but not committed yet, or None if all the ordered
+    /// blocks are committed.
+    fn oldest_uncommitted_round(&self) -> Option<Round>;
+
+    /// Return how long the commit root has not advanced while there are ordered blocks waiting
+    /// to be committed, or zero if all the ordered blocks are committed.
+    fn commit_stall_duration(&self) -> Duration;
+
+    /// Return the certs justifying the commit of the given block, or None if the block is not
+    /// committed or has already been pruned from memory.
+    fn commit_proof_for(&self, block_id: HashValue) -> Option<CommitProofChain>;
+
+    /// Return the signed ledger info committing the given block, or None if the block is not
+    /// committed or has already been pruned from memory.
+    fn committed_ledger_info(&self, block_id: HashValue) -> Option<LedgerInfoWithSignatures>;
+
+    /// Return the committed blocks with a round greater than the given one, ordered by round.
+    /// Only blocks still in memory are returned: the flag is set if some committed blocks were
+    /// omitted because they have already been pruned.
+    fn committed_blocks_since(&self, round: Round) -> (Vec<Arc<PipelinedBlock>>, bool);
+
+    /// Return the id of the block certified by the QC carried by the given block, or None if the
+    /// block is not found. Note that the QC links only differ from the parent links for DAG blocks.
+    fn qc_parent(&self, block_id: HashValue) -> Option<HashValue>;
+
+    /// Return the ids of the blocks on the path of QC links from the given block back to the commit
+    /// root (both included), or None if the QC links of the block do not lead to the commit root.
+    fn qc_path_to_root(&self, block_id: HashValue) -> Option<Vec<HashValue>>;
+
+    /// Return the pairs of distinct blocks proposed by the same author at the given round, i.e.
+    /// the equivocations found in the tree. Each pair is ordered by block id.
+    fn equivocations_at(&self, round: Round) -> Vec<(HashValue, HashValue)>;
+
+    /// Return the ids of the blocks in the tree whose QC has not been received yet, ordered by id.
+    fn blocks_missing_qc(&self) -> Vec<HashValue>;
+}
+
+/// Chooses which of the pruned blocks the `BlockTree` keeps in memory once there are more than
+/// `max_pruned_blocks_in_mem` of them, e.g. to retain blocks of abandoned forks for forensics.
+/// Without it, the most recently pruned blocks are kept.
+pub trait PrunedBlockRetention: Send + Sync {
+    /// Returns the ids of the blocks to keep among `pruned_blocks`, which are ordered from the
+    /// earliest to the most recently pruned. If more than `budget` ids are returned, the most
+    /// recently pruned of them are kept.
+    fn select_retained(
+        &self,
+        pruned_blocks: &[Arc<PipelinedBlock>],
+        budget: usize,
+    ) -> HashSet<HashValue>;
+}
+
+/// Stores the pruned blocks the `BlockTree` removes from memory, e.g. in a local on-disk store of
+/// an archival node, so that `BlockStore::archived_block` can still load them for forensic
+/// queries.
+pub trait PrunedBlockArchive: Send + Sync {
+    /// Stores a block right after it is removed from memory, without holding the tree lock.
+    fn store(&self, block: &PipelinedBlock);
+
+    /// Loads a previously stored block, or returns None if there is no such block.
+    fn load(&self, block_id: HashValue) -> Option<PipelinedBlock>;
+}
+
+/// The certs justifying the commit of a block, see `BlockReader::commit_proof_for`.
+#[derive(Clone, Debug)]
+pub struct CommitProofChain {
+    /// The quorum cert certifying the block.
+    pub quorum_cert: Arc<QuorumCert>,
+    /// The highest ordered cert, which orders the block together with its descendants up to the
+    /// ordered root.
+    pub ordered_cert: Arc<WrappedLedgerInfo>,
+    /// The cert committing the block, the same as `BlockReader::committed_ledger_info`: one that
+    /// commits exactly the block while it is in memory, otherwise the highest commit cert, which
+    /// commits the block together with its descendants up to the commit root.
+    pub commit_cert: Arc<WrappedLedgerInfo>,
+}
+
+/// Decides whether the `BlockStore` prefetches the payload of a block it inserts, e.g. to save
+/// bandwidth on low priority blocks or blocks of abandoned forks. Without it, the payloads of all
+/// inserted blocks are prefetched.
+pub trait PrefetchPolicy: Send + Sync {
+    fn should_prefetch(&self, block: &Block) -> bool;
+}
+
+/// Notified by the `BlockStore` whenever blocks get committed, e.g. to let external indexers react
+/// to commits. Invoked after the block tree has been updated, without holding the tree lock.
+pub trait CommitListener: Send + Sync {
+    fn on_commit(
+        &self,
+        committed_block_ids: &[HashValue],
+        commit_decision: &LedgerInfoWithSignatures,
+    );
+}
+
+/// Observes every write the `BlockStore` issues via `PersistentLivenessStorage::save_tree`, e.g. to
+/// keep an audit trail when debugging storage divergence. Invoked right before the write happens.
+pub trait StorageWriteObserver: Send + Sync {
+    fn on_save_tree(&self, blocks: &[Block], quorum_certs: &[QuorumCert]);
 }
@@ -125,7 +125,7 @@ impl BlockStore {
         // Even though we inserted the highest_quorum_cert (and its ancestors) in the above step,
         // we still need to insert ordered cert explicitly. This will send the highest ordered block
         // to execution.
-        if self.order_vote_enabled {
+        if self.order_vote_enabled() {
             self.insert_ordered_cert(&sync_info.highest_ordered_cert())
                 .await?;
         } else {
@@ -138,7 +138,7 @@ impl BlockStore {
                     .highest_ordered_cert()
                     .as_ref()
                     .clone()
-                    .into_quorum_cert(self.order_vote_enabled)?,
+                    .into_quorum_cert(self.order_vote_enabled())?,
                 &mut retriever,
             )
             .await?;
@@ -266,7 +266,7 @@ impl BlockStore {
             self.storage.clone(),
             self.execution_client.clone(),
             self.payload_manager.clone(),
-            self.order_vote_enabled,
+            self.order_vote_enabled(),
         )
         .await?
         .take();
@@ -785,6 +785,43 @@ pub static NUM_BLOCKS_IN_TREE: Lazy<IntGauge> = Lazy::new(|| {
     .unwrap()
 });
 
+/// Rough estimate of the memory used by the block tree, including payloads and quorum certs.
+pub static BLOCK_TREE_ESTIMATED_MEMORY_BYTES: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "aptos_consensus_block_tree_estimated_memory_bytes",
+        "Rough estimate of the memory used by the block tree, including payloads and quorum certs."
+    )
+    .unwrap()
+});
+
+/// Lowest round that is ordered but not yet committed, 0 if all the ordered blocks are committed.
+pub static OLDEST_UNCOMMITTED_ROUND: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "aptos_consensus_oldest_uncommitted_round",
+        "Lowest round that is ordered but not yet committed, 0 if all the ordered blocks are committed."
+    )
+    .unwrap()
+});
+
+/// Counts the times a timeout certificate with a higher round than the previous highest one is
+/// installed in the block store.
+pub static HIGHEST_TIMEOUT_CERT_UPDATES: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "aptos_consensus_highest_timeout_cert_updates",
+        "Number of times a higher timeout certificate is installed in the block store."
+    )
+    .unwrap()
+});
+
+/// Seconds since the commit root last advanced while ordered blocks are waiting to be committed.
+pub static COMMIT_STALL_DURATION: Lazy<Gauge> = Lazy::new(|| {
+    register_gauge!(
+        "aptos_consensus_commit_stall_duration_s",
+        "Seconds since the commit root last advanced while ordered blocks are waiting to be committed."
+    )
+    .unwrap()
+});
+
 /// Counter for the number of blocks in the pipeline broken down by stage.
 pub static NUM_BLOCKS_IN_PIPELINE: Lazy<IntGaugeVec> = Lazy::new(|| {
     register_int_gauge_vec!(
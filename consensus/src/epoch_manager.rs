@@ -836,18 +836,25 @@ impl<P: OnChainConfigProvider> EpochManager<P> {
         info!(epoch = epoch, "Create BlockStore");
         // Read the last vote, before "moving" `recovery_data`
         let last_vote = recovery_data.last_vote();
-        let block_store = Arc::new(BlockStore::new(
-            Arc::clone(&self.storage),
-            recovery_data,
-            self.execution_client.clone(),
-            self.config.max_pruned_blocks_in_mem,
-            Arc::clone(&self.time_service),
-            self.config.vote_back_pressure_limit,
-            payload_manager,
-            onchain_consensus_config.order_vote_enabled(),
-            self.pending_blocks.clone(),
-            maybe_pipeline_builder,
-        ));
+        let mut block_store_builder = BlockStore::builder()
+            .storage(Arc::clone(&self.storage))
+            .execution_client(self.execution_client.clone())
+            .max_pruned_blocks_in_mem(self.config.max_pruned_blocks_in_mem)
+            .time_service(Arc::clone(&self.time_service))
+            .vote_back_pressure_limit(self.config.vote_back_pressure_limit)
+            .payload_manager(payload_manager)
+            .order_vote_enabled(onchain_consensus_config.order_vote_enabled())
+            .pending_blocks(self.pending_blocks.clone())
+            .enforce_timestamp_monotonicity(self.config.enforce_timestamp_monotonicity);
+        if let Some(pipeline_builder) = maybe_pipeline_builder {
+            block_store_builder = block_store_builder.pipeline_builder(pipeline_builder);
+        }
+        let block_store = Arc::new(
+            block_store_builder
+                .build(recovery_data)
+                .await
+                .expect("All required BlockStore fields are set"),
+        );
 
         let failures_tracker = Arc::new(Mutex::new(ExponentialWindowFailureTracker::new(
             100,
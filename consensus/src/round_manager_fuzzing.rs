@@ -5,7 +5,7 @@
 #![allow(clippy::unwrap_used)]
 
 use crate::{
-    block_storage::{pending_blocks::PendingBlocks, BlockStore},
+    block_storage::BlockStore,
     liveness::{
         proposal_generator::{
             ChainHealthBackoffConfig, PipelineBackpressureConfig, ProposalGenerator,
@@ -83,18 +83,15 @@ fn build_empty_store(
 ) -> Arc<BlockStore> {
     let (_commit_cb_sender, _commit_cb_receiver) = mpsc::unbounded::<LedgerInfoWithSignatures>();
 
-    Arc::new(BlockStore::new(
-        storage,
-        initial_data,
-        Arc::new(DummyExecutionClient),
-        10, // max pruned blocks in mem
-        Arc::new(SimulatedTimeService::new()),
-        10,
-        Arc::from(DirectMempoolPayloadManager::new()),
-        false,
-        Arc::new(Mutex::new(PendingBlocks::new())),
-        None,
-    ))
+    let builder = BlockStore::builder()
+        .storage(storage)
+        .execution_client(Arc::new(DummyExecutionClient))
+        .max_pruned_blocks_in_mem(10)
+        .time_service(Arc::new(SimulatedTimeService::new()))
+        .vote_back_pressure_limit(10)
+        .payload_manager(Arc::from(DirectMempoolPayloadManager::new()))
+        .enforce_timestamp_monotonicity(false);
+    Arc::new(block_on(builder.build(initial_data)).unwrap())
 }
 
 // helpers for safety rule initialization
@@ -3,7 +3,7 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use crate::{
-    block_storage::{pending_blocks::PendingBlocks, BlockReader, BlockStore},
+    block_storage::{BlockReader, BlockStore},
     counters,
     liveness::{
         proposal_generator::{
@@ -283,18 +283,15 @@ impl NodeSetup {
         ));
         let time_service = Arc::new(ClockTimeService::new(executor));
 
-        let block_store = Arc::new(BlockStore::new(
-            storage.clone(),
-            initial_data,
-            mock_execution_client.clone(),
-            10, // max pruned blocks in mem
-            time_service.clone(),
-            10,
-            Arc::from(DirectMempoolPayloadManager::new()),
-            false,
-            Arc::new(Mutex::new(PendingBlocks::new())),
-            None,
-        ));
+        let block_store_builder = BlockStore::builder()
+            .storage(storage.clone())
+            .execution_client(mock_execution_client.clone())
+            .max_pruned_blocks_in_mem(10)
+            .time_service(time_service.clone())
+            .vote_back_pressure_limit(10)
+            .payload_manager(Arc::from(DirectMempoolPayloadManager::new()))
+            .enforce_timestamp_monotonicity(false);
+        let block_store = Arc::new(block_on(block_store_builder.build(initial_data)).unwrap());
 
         let proposer_election = Self::create_proposer_election(proposers.clone());
         let proposal_generator = ProposalGenerator::new(
@@ -838,7 +835,7 @@ fn delay_proposal_processing_in_sync_only() {
         // Clear the sync only mode and process verified proposal and ensure it is processed now
         node.round_manager
             .block_store
-            .set_back_pressure_for_test(false);
+            .clear_back_pressure_for_test();
 
         node.round_manager
             .process_verified_proposal(proposal)
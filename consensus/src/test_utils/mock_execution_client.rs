@@ -21,20 +21,42 @@ use aptos_consensus_types::{
     pipelined_block::PipelinedBlock,
 };
 use aptos_crypto::{bls12381::PrivateKey, HashValue};
-use aptos_executor_types::ExecutorResult;
+use aptos_executor_types::{ExecutorError, ExecutorResult};
 use aptos_infallible::Mutex;
 use aptos_logger::prelude::*;
 use aptos_types::{
     epoch_state::EpochState,
     ledger_info::LedgerInfoWithSignatures,
-    on_chain_config::{OnChainConsensusConfig, OnChainExecutionConfig, OnChainRandomnessConfig},
+    on_chain_config::{
+        OnChainConsensusConfig, OnChainExecutionConfig, OnChainRandomnessConfig, ValidatorSet,
+    },
     transaction::SignedTransaction,
     validator_signer::ValidatorSigner,
 };
 use futures::{channel::mpsc, SinkExt};
 use futures_channel::mpsc::UnboundedSender;
 use move_core_types::account_address::AccountAddress;
-use std::{collections::HashMap, sync::Arc, time::Duration};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+    time::Duration,
+};
+
+/// How `MockExecutionClient::finalize_order` handles the ordered blocks.
+pub enum FinalizeOrderBehavior {
+    /// Sends the ordered blocks to the execution channel, to be committed by `commit_to_storage`.
+    SendToExecutionChannel,
+    /// Commits the ordered blocks right away, by invoking the commit callback with the ordered
+    /// proof from within `finalize_order`.
+    CommitImmediately,
+    /// Never completes, to simulate a stalled execution.
+    Stall,
+    /// Commits the ordered blocks right away, but then never completes, e.g. to abort
+    /// `finalize_order` after the commit took effect.
+    CommitThenStall,
+    /// Fails for the given commit rounds and commits the other blocks immediately.
+    FailRounds(HashSet<Round>),
+}
 
 pub struct MockExecutionClient {
     state_sync_client: mpsc::UnboundedSender<Vec<SignedTransaction>>,
@@ -42,6 +64,7 @@ pub struct MockExecutionClient {
     consensus_db: Arc<MockStorage>,
     block_cache: Mutex<HashMap<HashValue, Payload>>,
     payload_manager: Arc<dyn TPayloadManager>,
+    finalize_order_behavior: FinalizeOrderBehavior,
 }
 
 impl MockExecutionClient {
@@ -56,6 +79,19 @@ impl MockExecutionClient {
             consensus_db,
             block_cache: Mutex::new(HashMap::new()),
             payload_manager: Arc::from(DirectMempoolPayloadManager::new()),
+            finalize_order_behavior: FinalizeOrderBehavior::SendToExecutionChannel,
+        }
+    }
+
+    /// Returns a client with the given `finalize_order` behavior, whose execution channel, state
+    /// sync notifications and storage are not observed by anyone.
+    pub fn with_finalize_order_behavior(finalize_order_behavior: FinalizeOrderBehavior) -> Self {
+        let (state_sync_client, _) = mpsc::unbounded();
+        let (executor_channel, _) = mpsc::unbounded();
+        let (_, consensus_db) = MockStorage::start_for_testing(ValidatorSet::empty());
+        Self {
+            finalize_order_behavior,
+            ..Self::new(state_sync_client, executor_channel, consensus_db)
         }
     }
 
@@ -120,6 +156,28 @@ impl TExecutionClient for MockExecutionClient {
         callback: StateComputerCommitCallBackType,
     ) -> ExecutorResult<()> {
         assert!(!blocks.is_empty());
+        match &self.finalize_order_behavior {
+            FinalizeOrderBehavior::SendToExecutionChannel => {},
+            FinalizeOrderBehavior::CommitImmediately => {
+                callback(blocks, finality_proof);
+                return Ok(());
+            },
+            FinalizeOrderBehavior::Stall => return futures::future::pending().await,
+            FinalizeOrderBehavior::CommitThenStall => {
+                callback(blocks, finality_proof);
+                return futures::future::pending().await;
+            },
+            FinalizeOrderBehavior::FailRounds(failing_rounds) => {
+                let commit_round = finality_proof.commit_info().round();
+                if failing_rounds.contains(&commit_round) {
+                    return Err(ExecutorError::InternalError {
+                        error: format!("Injected failure for round {}", commit_round),
+                    });
+                }
+                callback(blocks, finality_proof);
+                return Ok(());
+            },
+        }
         info!(
             "MockStateComputer commit put on queue {:?}",
             blocks.iter().map(|v| v.round()).collect::<Vec<_>>()
@@ -34,17 +34,16 @@ mod mock_state_computer;
 mod mock_storage;
 
 use crate::{
-    block_storage::pending_blocks::PendingBlocks, pipeline::execution_client::DummyExecutionClient,
-    util::mock_time_service::SimulatedTimeService,
+    pipeline::execution_client::DummyExecutionClient, util::mock_time_service::SimulatedTimeService,
 };
 use aptos_consensus_types::{block::block_test_utils::gen_test_certificate, common::Payload};
 use aptos_crypto::ed25519::{Ed25519PrivateKey, Ed25519Signature};
-use aptos_infallible::Mutex;
 use aptos_types::{
     block_info::BlockInfo,
     chain_id::ChainId,
     transaction::{RawTransaction, Script, SignedTransaction, TransactionPayload},
 };
+use futures::executor::block_on;
 pub use mock_payload_manager::MockPayloadManager;
 #[cfg(test)]
 pub use mock_state_computer::EmptyStateComputer;
@@ -91,18 +90,15 @@ pub async fn build_simple_tree() -> (Vec<Arc<PipelinedBlock>>, Arc<BlockStore>)
 
 pub fn build_empty_tree() -> Arc<BlockStore> {
     let (initial_data, storage) = EmptyStorage::start_for_testing();
-    Arc::new(BlockStore::new(
-        storage,
-        initial_data,
-        Arc::new(DummyExecutionClient),
-        10, // max pruned blocks in mem
-        Arc::new(SimulatedTimeService::new()),
-        10,
-        Arc::from(DirectMempoolPayloadManager::new()),
-        false,
-        Arc::new(Mutex::new(PendingBlocks::new())),
-        None,
-    ))
+    let builder = BlockStore::builder()
+        .storage(storage)
+        .execution_client(Arc::new(DummyExecutionClient))
+        .max_pruned_blocks_in_mem(10)
+        .time_service(Arc::new(SimulatedTimeService::new()))
+        .vote_back_pressure_limit(10)
+        .payload_manager(Arc::from(DirectMempoolPayloadManager::new()))
+        .enforce_timestamp_monotonicity(false);
+    Arc::new(block_on(builder.build(initial_data)).unwrap())
 }
 
 pub struct TreeInserter {
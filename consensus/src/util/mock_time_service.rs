@@ -6,7 +6,7 @@ use crate::util::time_service::{ScheduledTask, TimeService};
 use aptos_infallible::Mutex;
 use aptos_logger::prelude::*;
 use async_trait::async_trait;
-use futures::future::AbortHandle;
+use futures::future::{AbortHandle, AbortRegistration, Abortable};
 use std::{sync::Arc, time::Duration};
 
 /// SimulatedTimeService implements TimeService, however it does not depend on actual time
@@ -136,3 +136,163 @@ impl Clone for SimulatedTimeService {
         }
     }
 }
+
+/// A source of time that only moves when it is explicitly advanced. Used to make tests that depend
+/// on block timestamps deterministic.
+pub trait LogicalClock: Send + Sync {
+    /// Returns the current logical time (since UNIX_EPOCH).
+    fn now(&self) -> Duration;
+
+    /// Moves the clock forward to the given time, the clock never moves backwards.
+    fn advance_to(&self, t: Duration);
+}
+
+/// A logical clock that starts at 0 and only moves via `advance_to`.
+#[derive(Default)]
+pub struct ManualClock {
+    now: Mutex<Duration>,
+}
+
+impl LogicalClock for ManualClock {
+    fn now(&self) -> Duration {
+        *self.now.lock()
+    }
+
+    fn advance_to(&self, t: Duration) {
+        let mut now = self.now.lock();
+        if t > *now {
+            *now = t;
+        }
+    }
+}
+
+/// TimeService driven by a LogicalClock. Sleeping (and thus waiting) advances the clock instead of
+/// waiting for real time to pass, so it resolves immediately. Scheduled tasks are queued, and run
+/// once the clock is advanced to their deadline by `advance_to` or `sleep`.
+pub struct LogicalClockTimeService {
+    clock: Arc<dyn LogicalClock>,
+    pending: Mutex<Vec<(Duration, Box<dyn ScheduledTask>, AbortRegistration)>>,
+}
+
+impl LogicalClockTimeService {
+    pub fn new(clock: Arc<dyn LogicalClock>) -> Self {
+        Self {
+            clock,
+            pending: Mutex::new(vec![]),
+        }
+    }
+
+    /// Moves the clock forward to the given time, and runs the pending tasks whose deadline has
+    /// passed, earliest deadline first. Tasks scheduled while running them are only run by a later
+    /// call, even if they are already due, so that rescheduling tasks can't run forever.
+    pub fn advance_to(&self, t: Duration) {
+        self.clock.advance_to(t);
+        let now = self.clock.now();
+        let mut due: Vec<_> = {
+            let mut pending = self.pending.lock();
+            let (due, not_due): (Vec<_>, Vec<_>) = std::mem::take(&mut *pending)
+                .into_iter()
+                .partition(|(deadline, _, _)| *deadline <= now);
+            *pending = not_due;
+            due
+        };
+        due.sort_by_key(|(deadline, _, _)| *deadline);
+        for (_, mut t, registration) in due {
+            // Tasks aborted through their handle resolve without running.
+            let _ = futures::executor::block_on(Abortable::new(t.run(), registration));
+        }
+    }
+}
+
+#[async_trait]
+impl TimeService for LogicalClockTimeService {
+    fn run_after(&self, timeout: Duration, t: Box<dyn ScheduledTask>) -> AbortHandle {
+        let (handle, registration) = AbortHandle::new_pair();
+        let deadline = self.clock.now() + timeout;
+        self.pending.lock().push((deadline, t, registration));
+        handle
+    }
+
+    fn get_current_timestamp(&self) -> Duration {
+        self.clock.now()
+    }
+
+    async fn sleep(&self, t: Duration) {
+        self.advance_to(self.clock.now() + t);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::{executor::block_on, Future};
+    use std::pin::Pin;
+
+    /// Records its name and the logical time at which it runs, and reschedules itself right away
+    /// if `time_service` is set.
+    struct RecordingTask {
+        name: &'static str,
+        clock: Arc<ManualClock>,
+        runs: Arc<Mutex<Vec<(&'static str, Duration)>>>,
+        time_service: Option<Arc<LogicalClockTimeService>>,
+    }
+
+    impl ScheduledTask for RecordingTask {
+        fn run(&mut self) -> Pin<Box<dyn Future<Output = ()> + Send>> {
+            let (name, clock, runs) = (self.name, self.clock.clone(), self.runs.clone());
+            if let Some(time_service) = &self.time_service {
+                time_service.run_after(
+                    Duration::ZERO,
+                    Box::new(RecordingTask {
+                        name,
+                        clock: clock.clone(),
+                        runs: runs.clone(),
+                        time_service: Some(time_service.clone()),
+                    }),
+                );
+            }
+            Box::pin(async move { runs.lock().push((name, clock.now())) })
+        }
+    }
+
+    #[test]
+    fn test_logical_clock_time_service() {
+        let clock = Arc::new(ManualClock::default());
+        let time_service = Arc::new(LogicalClockTimeService::new(clock.clone()));
+        let runs = Arc::new(Mutex::new(vec![]));
+        let task = |name, time_service: Option<&Arc<LogicalClockTimeService>>| {
+            Box::new(RecordingTask {
+                name,
+                clock: clock.clone(),
+                runs: runs.clone(),
+                time_service: time_service.cloned(),
+            })
+        };
+
+        time_service.run_after(Duration::from_secs(10), task("a", None));
+        time_service.run_after(Duration::from_secs(5), task("b", None));
+        time_service
+            .run_after(Duration::from_secs(1), task("c", None))
+            .abort();
+        assert!(runs.lock().is_empty());
+
+        // Tasks only run once the clock reaches their deadline, and aborted tasks never run.
+        time_service.advance_to(Duration::from_secs(4));
+        assert!(runs.lock().is_empty());
+        block_on(time_service.sleep(Duration::from_secs(6)));
+        assert_eq!(*runs.lock(), vec![
+            ("b", Duration::from_secs(10)),
+            ("a", Duration::from_secs(10)),
+        ]);
+
+        // A task rescheduling itself runs once per advance of the clock.
+        runs.lock().clear();
+        time_service.run_after(Duration::ZERO, task("d", Some(&time_service)));
+        time_service.advance_to(Duration::from_secs(11));
+        time_service.advance_to(Duration::from_secs(12));
+        assert_eq!(*runs.lock(), vec![
+            ("d", Duration::from_secs(11)),
+            ("d", Duration::from_secs(12)),
+        ]);
+    }
+}
@@ -0,0 +1,35 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::{smoke_test_environment::SwarmBuilder, utils::set_feature_flags};
+use aptos_forge::{NodeExt, Swarm};
+use aptos_types::on_chain_config::FeatureFlag;
+use std::{collections::BTreeMap, time::Duration};
+
+/// Disables and re-enables two feature flags at once, waiting for each change to take effect.
+#[tokio::test]
+async fn set_two_feature_flags_at_once() {
+    let (swarm, mut cli, _faucet) = SwarmBuilder::new_local(1)
+        .with_aptos()
+        .build_with_cli(0)
+        .await;
+    let root_addr = swarm.chain_info().root_account().address();
+    let root_idx = cli.add_account_with_address_to_cli(swarm.root_key(), root_addr);
+    let rest_client = swarm.validators().next().unwrap().rest_client();
+
+    for enabled in [false, true] {
+        let desired = BTreeMap::from([
+            (FeatureFlag::SHA_512_AND_RIPEMD_160_NATIVES as u64, enabled),
+            (FeatureFlag::BLAKE2B_256_NATIVE as u64, enabled),
+        ]);
+        set_feature_flags(
+            &cli,
+            root_idx,
+            &rest_client,
+            &desired,
+            Duration::from_secs(60),
+        )
+        .await
+        .unwrap();
+    }
+}
@@ -19,6 +19,8 @@ mod consensus_observer;
 #[cfg(test)]
 mod execution;
 #[cfg(test)]
+mod feature_flags;
+#[cfg(test)]
 mod full_nodes;
 #[cfg(test)]
 mod fullnode;
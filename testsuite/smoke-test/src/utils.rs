@@ -2,6 +2,8 @@
 // Parts of the project are originally copyright © Meta Platforms, Inc.
 // SPDX-License-Identifier: Apache-2.0
 
+use anyhow::ensure;
+use aptos::test::CliTestFramework;
 use aptos_cached_packages::aptos_stdlib;
 use aptos_config::{
     config::{NodeConfig, Peer, PeerRole, HANDSHAKE_VERSION},
@@ -15,11 +17,17 @@ use aptos_sdk::{
 };
 use aptos_types::{
     network_address::{NetworkAddress, Protocol},
-    on_chain_config::{OnChainConfig, OnChainConsensusConfig, OnChainExecutionConfig},
+    on_chain_config::{Features, OnChainConfig, OnChainConsensusConfig, OnChainExecutionConfig},
 };
 use move_core_types::language_storage::CORE_CODE_ADDRESS;
 use rand::random;
-use std::{collections::HashSet, net::Ipv4Addr, sync::Arc, time::Duration};
+use std::{
+    collections::{BTreeMap, HashSet},
+    net::Ipv4Addr,
+    sync::Arc,
+    time::Duration,
+};
+use tokio::time::Instant;
 
 pub const MAX_CATCH_UP_WAIT_SECS: u64 = 180; // The max time we'll wait for nodes to catch up
 pub const MAX_CONNECTIVITY_WAIT_SECS: u64 = 180; // The max time we'll wait for nodes to gain connectivity
@@ -283,6 +291,69 @@ pub async fn get_on_chain_resource<T: OnChainConfig>(rest_client: &Client) -> T
     response.into_inner()
 }
 
+/// Enables and disables the given feature flags (by id) with a single governance script run by
+/// the root account at `root_idx`, then polls the on-chain `Features` until all of them are in
+/// the desired state. Returns an error if the script fails or the flags don't take effect within
+/// `timeout`.
+pub async fn set_feature_flags(
+    cli: &CliTestFramework,
+    root_idx: usize,
+    rest_client: &RestClient,
+    desired: &BTreeMap<u64, bool>,
+    timeout: Duration,
+) -> anyhow::Result<()> {
+    let feature_ids = |enabled: bool| {
+        desired
+            .iter()
+            .filter(|(_, on)| **on == enabled)
+            .map(|(id, _)| id.to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    let script = format!(
+        r#"
+script {{
+    use aptos_framework::aptos_governance;
+    use std::features;
+
+    fun main(core_resources: &signer) {{
+        let framework_signer = aptos_governance::get_signer_testnet_only(core_resources, @0x1);
+        features::change_feature_flags_for_next_epoch(&framework_signer, vector[{}], vector[{}]);
+        aptos_governance::reconfigure(&framework_signer);
+    }}
+}}
+"#,
+        feature_ids(true),
+        feature_ids(false)
+    );
+    cli.run_script(root_idx, &script).await?;
+
+    let timer = Instant::now();
+    loop {
+        let features = get_on_chain_resource::<Features>(rest_client).await;
+        if desired
+            .iter()
+            .all(|(id, enabled)| is_feature_enabled(&features, *id) == *enabled)
+        {
+            return Ok(());
+        }
+        ensure!(
+            timer.elapsed() < timeout,
+            "Feature flags {:?} did not take effect within {:?}",
+            desired,
+            timeout
+        );
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+}
+
+fn is_feature_enabled(features: &Features, id: u64) -> bool {
+    features
+        .features
+        .get((id / 8) as usize)
+        .map_or(false, |byte| byte & (1 << (id % 8)) != 0)
+}
+
 #[cfg(test)]
 pub mod swarm_utils {
     use aptos_config::config::{NodeConfig, SecureBackend, WaypointConfig};
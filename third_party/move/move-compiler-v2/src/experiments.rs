@@ -17,6 +17,9 @@
 //! turns on or off a bunch of other experiments, unless those are
 //! defined explicitly.
 
+use crate::Options;
+use itertools::Itertools;
+use move_core_types::metadata::Metadata;
 use once_cell::sync::Lazy;
 use std::collections::BTreeMap;
 
@@ -235,6 +238,13 @@ pub static EXPERIMENTS: Lazy<BTreeMap<String, Experiment>> = Lazy::new(|| {
             description: "Whether to attach the compiled module to the global env.".to_string(),
             default: Given(false),
         },
+        Experiment {
+            name: Experiment::ATTACH_EXPERIMENTS_METADATA.to_string(),
+            description: "Whether to record the optimization experiments which are on \
+            in the metadata of compiled modules."
+                .to_string(),
+            default: Given(false),
+        },
         Experiment {
             name: Experiment::LINT_CHECKS.to_string(),
             description: "Whether to run various lint checks.".to_string(),
@@ -291,6 +301,7 @@ impl Experiment {
     pub const AST_SIMPLIFY: &'static str = "ast-simplify";
     pub const AST_SIMPLIFY_FULL: &'static str = "ast-simplify-full";
     pub const ATTACH_COMPILED_MODULE: &'static str = "attach-compiled-module";
+    pub const ATTACH_EXPERIMENTS_METADATA: &'static str = "attach-experiments-metadata";
     pub const CFG_SIMPLIFICATION: &'static str = "cfg-simplification";
     pub const CHECKS: &'static str = "checks";
     pub const COPY_PROPAGATION: &'static str = "copy-propagation";
@@ -331,3 +342,191 @@ impl Experiment {
     pub const VARIABLE_COALESCING: &'static str = "variable-coalescing";
     pub const VARIABLE_COALESCING_ANNOTATE: &'static str = "variable-coalescing-annotate";
 }
+
+impl Experiment {
+    /// The experiments controlling optimizations, whose state is recorded in module metadata
+    /// by `ATTACH_EXPERIMENTS_METADATA`.
+    pub const OPTIMIZATIONS: &'static [&'static str] = &[
+        Experiment::AST_SIMPLIFY,
+        Experiment::AST_SIMPLIFY_FULL,
+        Experiment::CFG_SIMPLIFICATION,
+        Experiment::COPY_PROPAGATION,
+        Experiment::DEAD_CODE_ELIMINATION,
+        Experiment::FLUSH_WRITES_OPTIMIZATION,
+        Experiment::INLINING,
+        Experiment::PEEPHOLE_OPTIMIZATION,
+        Experiment::SPLIT_CRITICAL_EDGES,
+        Experiment::VARIABLE_COALESCING,
+    ];
+    /// The experiments which are consulted per module, and can therefore be overridden for
+    /// individual modules via `Options::set_module_experiment`.
+    pub const PER_MODULE: &'static [&'static str] = &[
+        Experiment::CFG_SIMPLIFICATION,
+        Experiment::COPY_PROPAGATION,
+        Experiment::PEEPHOLE_OPTIMIZATION,
+        Experiment::VARIABLE_COALESCING,
+    ];
+}
+
+/// How an experiment relates to the output of the compiler.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ExperimentKind {
+    /// The experiment can change the emitted bytecode or module metadata.
+    OutputAffecting,
+    /// The experiment only changes what is reported or kept around for inspection and testing.
+    DiagnosticOnly,
+    /// The experiment only turns checks on or off, which can reject programs but does not
+    /// change the output for accepted ones.
+    CheckOnly,
+}
+
+impl Experiment {
+    /// Returns how this experiment relates to the output of the compiler. Experiments which are
+    /// not classified explicitly are conservatively assumed to affect the output.
+    pub fn kind(&self) -> ExperimentKind {
+        use ExperimentKind::*;
+        match self.name.as_str() {
+            Experiment::ACCESS_CHECK
+            | Experiment::ACQUIRES_CHECK
+            | Experiment::DUPLICATE_STRUCT_PARAMS_CHECK
+            | Experiment::LAMBDA_IN_PARAMS
+            | Experiment::LAMBDA_IN_RETURNS
+            | Experiment::LINT_CHECKS
+            | Experiment::RECURSIVE_TYPE_CHECK
+            | Experiment::REFERENCE_SAFETY
+            | Experiment::SEQS_IN_BINOPS_CHECK
+            | Experiment::SPEC_CHECK
+            | Experiment::UNINITIALIZED_CHECK
+            | Experiment::UNUSED_ASSIGNMENT_CHECK
+            | Experiment::UNUSED_STRUCT_PARAMS_CHECK
+            | Experiment::USAGE_CHECK => CheckOnly,
+            Experiment::ATTACH_COMPILED_MODULE
+            | Experiment::KEEP_UNINIT_ANNOTATIONS
+            | Experiment::MESSAGE_FORMAT_JSON
+            | Experiment::SPEC_REWRITE
+            | Experiment::STOP_AFTER_EXTENDED_CHECKS
+            | Experiment::STOP_BEFORE_EXTENDED_CHECKS
+            | Experiment::STOP_BEFORE_FILE_FORMAT
+            | Experiment::STOP_BEFORE_STACKLESS_BYTECODE
+            | Experiment::VARIABLE_COALESCING_ANNOTATE => DiagnosticOnly,
+            // The ability and reference safety analyses also decide which values are copied or
+            // moved and dropped in the generated code, and `CHECKS` turns the ability check on
+            // or off, so these are not mere checks.
+            _ => OutputAffecting,
+        }
+    }
+}
+
+/// Returns the names of all declared experiments which can affect the output of the compiler,
+/// in alphabetical order.
+pub fn output_affecting_experiments() -> Vec<&'static str> {
+    EXPERIMENTS
+        .iter()
+        .filter(|(_, experiment)| experiment.kind() == ExperimentKind::OutputAffecting)
+        .map(|(name, _)| name.as_str())
+        .collect()
+}
+
+/// The key of the module metadata entry produced by `experiments_metadata`.
+pub static EXPERIMENTS_METADATA_KEY: &[u8] = "compiler_experiments".as_bytes();
+
+/// Encodes the names of the optimization experiments which are on in `options` for the module
+/// with the given fully qualified name (taking per-module overrides into account) as a module
+/// metadata entry.
+pub fn experiments_metadata(options: &Options, module_name: &str) -> Metadata {
+    let enabled = Experiment::OPTIMIZATIONS
+        .iter()
+        .filter(|name| options.experiment_on_in_module(module_name, name))
+        .map(|name| name.to_string())
+        .collect_vec();
+    Metadata {
+        key: EXPERIMENTS_METADATA_KEY.to_vec(),
+        value: bcs::to_bytes(&enabled).expect("Serialization of experiments should succeed"),
+    }
+}
+
+/// Decodes the optimization experiments recorded by `experiments_metadata`, if the
+/// metadata contains such an entry.
+pub fn optimization_experiments_from_metadata(metadata: &[Metadata]) -> Option<Vec<String>> {
+    metadata
+        .iter()
+        .find(|entry| entry.key == EXPERIMENTS_METADATA_KEY)
+        .and_then(|entry| bcs::from_bytes(&entry.value).ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use move_binary_format::{file_format::empty_module, CompiledModule};
+
+    #[test]
+    fn test_experiments_metadata_round_trip() {
+        let options = Options::default()
+            .set_experiment(Experiment::COPY_PROPAGATION, true)
+            .set_experiment(Experiment::INLINING, false);
+        let mut module = empty_module();
+        assert_eq!(
+            optimization_experiments_from_metadata(&module.metadata),
+            None
+        );
+        module
+            .metadata
+            .push(experiments_metadata(&options, "0x42::m"));
+
+        let mut bytes = vec![];
+        module.serialize(&mut bytes).expect("module serializes");
+        let module = CompiledModule::deserialize(&bytes).expect("module deserializes");
+        let enabled = optimization_experiments_from_metadata(&module.metadata)
+            .expect("experiments metadata present");
+        assert!(enabled.contains(&Experiment::COPY_PROPAGATION.to_string()));
+        assert!(!enabled.contains(&Experiment::INLINING.to_string()));
+        assert!(enabled
+            .iter()
+            .all(|name| Experiment::OPTIMIZATIONS.contains(&name.as_str())));
+    }
+
+    #[test]
+    fn test_experiments_metadata_module_overrides() {
+        let options = Options::default()
+            .set_experiment(Experiment::PEEPHOLE_OPTIMIZATION, true)
+            .set_module_experiment("0x42::excluded", Experiment::PEEPHOLE_OPTIMIZATION, false);
+        let enabled = |module_name| {
+            optimization_experiments_from_metadata(&[experiments_metadata(&options, module_name)])
+                .expect("experiments metadata present")
+        };
+        let peephole = Experiment::PEEPHOLE_OPTIMIZATION.to_string();
+        assert!(enabled("0x42::included").contains(&peephole));
+        assert!(!enabled("0x42::excluded").contains(&peephole));
+    }
+
+    #[test]
+    fn test_experiment_kinds() {
+        let kind = |name: &str| EXPERIMENTS[name].kind();
+        assert_eq!(
+            kind(Experiment::VARIABLE_COALESCING),
+            ExperimentKind::OutputAffecting
+        );
+        assert_eq!(
+            kind(Experiment::VARIABLE_COALESCING_ANNOTATE),
+            ExperimentKind::DiagnosticOnly
+        );
+        assert_eq!(kind(Experiment::USAGE_CHECK), ExperimentKind::CheckOnly);
+        assert_eq!(
+            kind(Experiment::REFERENCE_SAFETY_V3),
+            ExperimentKind::OutputAffecting
+        );
+        assert_eq!(
+            kind(Experiment::ABILITY_CHECK),
+            ExperimentKind::OutputAffecting
+        );
+
+        let output_affecting = output_affecting_experiments();
+        assert!(output_affecting.contains(&Experiment::PEEPHOLE_OPTIMIZATION));
+        assert!(!output_affecting.contains(&Experiment::VARIABLE_COALESCING_ANNOTATE));
+        assert!(!output_affecting.contains(&Experiment::LINT_CHECKS));
+        // All optimizations recorded in module metadata affect the output.
+        assert!(Experiment::OPTIMIZATIONS
+            .iter()
+            .all(|name| output_affecting.contains(name)));
+    }
+}
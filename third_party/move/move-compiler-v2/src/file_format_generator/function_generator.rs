@@ -158,7 +158,10 @@ impl<'a> FunctionGenerator<'a> {
                     .env
                     .get_extension::<Options>()
                     .expect("Options is available");
-                if options.experiment_on(Experiment::PEEPHOLE_OPTIMIZATION) {
+                if options.experiment_on_in_module(
+                    &fun_env.module_env.get_full_name_str(),
+                    Experiment::PEEPHOLE_OPTIMIZATION,
+                ) {
                     let transformed_code_chunk = peephole_optimizer::optimize(&code.code);
                     // Fix the source map for the optimized code.
                     fun_gen
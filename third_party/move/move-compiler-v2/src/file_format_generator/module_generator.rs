@@ -2,6 +2,7 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use crate::{
+    experiments_metadata,
     file_format_generator::{
         function_generator::FunctionGenerator, MAX_ADDRESS_COUNT, MAX_CONST_COUNT, MAX_FIELD_COUNT,
         MAX_FIELD_INST_COUNT, MAX_FUNCTION_COUNT, MAX_FUNCTION_INST_COUNT, MAX_IDENTIFIER_COUNT,
@@ -133,10 +134,17 @@ impl ModuleGenerator {
             value: bcs::to_bytes(&compilation_metadata)
                 .expect("Serialization of CompilationMetadata should succeed"),
         };
+        let mut metadata = vec![metadata];
+        if options.experiment_on(Experiment::ATTACH_EXPERIMENTS_METADATA) {
+            metadata.push(experiments_metadata(
+                &options,
+                &module_env.get_full_name_str(),
+            ));
+        }
         let module = move_binary_format::CompiledModule {
             version: file_format_common::VERSION_MAX,
             self_module_handle_idx: FF::ModuleHandleIndex(0),
-            metadata: vec![metadata],
+            metadata,
             ..Default::default()
         };
         let source_map = {
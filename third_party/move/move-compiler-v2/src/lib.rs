@@ -64,14 +64,16 @@ use crate::{
         variable_coalescing::VariableCoalescing,
     },
 };
-
 // 引入外部 crate
 // 代码位置报告相关
 use codespan_reporting::{
     diagnostic::Severity,
     term::termcolor::{ColorChoice, StandardStream, WriteColor},
 };
-pub use experiments::{Experiment, EXPERIMENTS};
+pub use experiments::{
+    experiments_metadata, optimization_experiments_from_metadata, Experiment, EXPERIMENTS,
+    EXPERIMENTS_METADATA_KEY,
+};
 use log::{debug, info, log_enabled, Level};
 use move_binary_format::{binary_views::BinaryIndexedView, errors::VMError};
 use move_bytecode_source_map::source_map::SourceMap;
@@ -490,7 +492,9 @@ pub fn bytecode_pipeline(env: &GlobalEnv) -> FunctionTargetPipeline {
     // While this section of the pipeline is optional, some code that used to previously compile
     // may no longer compile without this section because of using too many local (temp) variables.
 
-    if options.experiment_on(Experiment::CFG_SIMPLIFICATION) {
+    // The following processors are added if the experiment is on for some module, and check
+    // for each function whether it is on for the function's module.
+    if options.experiment_on_in_any_module(Experiment::CFG_SIMPLIFICATION) {
         pipeline.add_processor(Box::new(ControlFlowGraphSimplifier {}));
         if options.experiment_on(Experiment::SPLIT_CRITICAL_EDGES) {
             // Currently, CFG simplification can again introduce critical edges, so
@@ -507,7 +511,7 @@ pub fn bytecode_pipeline(env: &GlobalEnv) -> FunctionTargetPipeline {
         pipeline.add_processor(Box::new(DeadStoreElimination::new(true)));
     }
 
-    if options.experiment_on(Experiment::VARIABLE_COALESCING) {
+    if options.experiment_on_in_any_module(Experiment::VARIABLE_COALESCING) {
         // Live var analysis is needed by variable coalescing.
         pipeline.add_processor(Box::new(LiveVarAnalysisProcessor::new(false)));
         if options.experiment_on(Experiment::VARIABLE_COALESCING_ANNOTATE) {
@@ -516,7 +520,7 @@ pub fn bytecode_pipeline(env: &GlobalEnv) -> FunctionTargetPipeline {
         pipeline.add_processor(Box::new(VariableCoalescing::transform_only()));
     }
 
-    if options.experiment_on(Experiment::COPY_PROPAGATION) {
+    if options.experiment_on_in_any_module(Experiment::COPY_PROPAGATION) {
         pipeline.add_processor(Box::new(AvailCopiesAnalysisProcessor {}));
         pipeline.add_processor(Box::new(CopyPropagation {}));
     }
@@ -3,7 +3,7 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use crate::{
-    experiments::{DefaultValue, EXPERIMENTS},
+    experiments::{DefaultValue, Experiment, EXPERIMENTS},
     external_checks::ExternalChecks,
 };
 use clap::Parser;
@@ -83,6 +83,17 @@ pub struct Options {
     #[clap(skip)]
     pub experiment_cache: RefCell<BTreeMap<String, bool>>,
 
+    /// Per-module experiment overrides, keyed by fully qualified module name (e.g. `0x42::m`).
+    /// An override takes precedence over the global setting of the experiment for that module.
+    #[clap(skip)]
+    pub module_experiments: BTreeMap<String, BTreeMap<String, bool>>,
+
+    /// The experiments set via `set_experiment`. Unlike the memoized values in
+    /// `experiment_cache`, these take precedence over per-module overrides of the experiments
+    /// they inherit from.
+    #[clap(skip)]
+    pub explicit_experiments: BTreeSet<String>,
+
     /// Sources to compile (positional arg, therefore last).
     /// Each source should be a path to either (1) a Move file or (2) a directory containing Move
     /// files, all to be compiled (e.g., not the root directory of a package---which contains
@@ -145,14 +156,51 @@ impl Options {
     }
 
     /// Turns an experiment on or off, overriding command line, environment, and defaults.
-    pub fn set_experiment(self, name: impl AsRef<str>, on: bool) -> Self {
+    pub fn set_experiment(mut self, name: impl AsRef<str>, on: bool) -> Self {
         let name = name.as_ref().to_string();
         assert!(
             EXPERIMENTS.contains_key(&name),
             "experiment `{}` not declared",
             name
         );
-        self.experiment_cache.borrow_mut().insert(name, on);
+        self.experiment_cache.borrow_mut().insert(name.clone(), on);
+        self.explicit_experiments.insert(name);
+        self
+    }
+
+    /// Turns an experiment on or off for the given module only, overriding the global setting.
+    /// The module is identified by its fully qualified name, e.g. `0x42::m`. Only experiments
+    /// in `Experiment::PER_MODULE`, or experiments those inherit their default from, can be
+    /// overridden per module.
+    pub fn set_module_experiment(
+        mut self,
+        module_name: impl AsRef<str>,
+        name: impl AsRef<str>,
+        on: bool,
+    ) -> Self {
+        let module_name = module_name.as_ref().to_string();
+        let name = name.as_ref().to_string();
+        assert!(
+            EXPERIMENTS.contains_key(&name),
+            "experiment `{}` not declared",
+            name
+        );
+        assert!(
+            module_name.contains("::"),
+            "module name `{}` is not fully qualified",
+            module_name
+        );
+        assert!(
+            Experiment::PER_MODULE
+                .iter()
+                .any(|exp| inheritance_chain(exp).contains(&name)),
+            "experiment `{}` is not consulted per module",
+            name
+        );
+        self.module_experiments
+            .entry(module_name)
+            .or_default()
+            .insert(name, on);
         self
     }
 
@@ -169,6 +217,45 @@ impl Options {
         self.experiment_on_recursive(name, &mut BTreeSet::new())
     }
 
+    /// Returns true if an experiment is on for the given fully qualified module name. A
+    /// per-module override of the experiment, or of an experiment it inherits its default
+    /// from, takes precedence, with the override closest in the inheritance chain winning.
+    /// However, an experiment explicitly set globally (see `experiment_set_explicitly`) is not
+    /// affected by the overrides of the experiments it inherits from. Otherwise this is the same
+    /// as `experiment_on`.
+    pub fn experiment_on_in_module(&self, module_name: &str, name: &str) -> bool {
+        if let Some(overrides) = self.module_experiments.get(module_name) {
+            for exp in inheritance_chain(name) {
+                if let Some(on) = overrides.get(&exp) {
+                    return *on;
+                }
+                if self.experiment_set_explicitly(&exp) {
+                    break;
+                }
+            }
+        }
+        self.experiment_on(name)
+    }
+
+    /// Returns true if an experiment is on globally or for some module. Passes consulting the
+    /// experiment per module are added to the pipeline if this is true, and then check
+    /// `experiment_on_in_module` for each function.
+    pub fn experiment_on_in_any_module(&self, name: &str) -> bool {
+        self.experiment_on(name)
+            || self
+                .module_experiments
+                .keys()
+                .any(|module_name| self.experiment_on_in_module(module_name, name))
+    }
+
+    /// Returns true if an experiment is given a value via `set_experiment`, the command line, or
+    /// the env var, rather than taking its default.
+    fn experiment_set_explicitly(&self, name: &str) -> bool {
+        self.explicit_experiments.contains(name)
+            || find_experiment(&self.experiments, name).is_some()
+            || find_experiment(&compiler_exp_var(), name).is_some()
+    }
+
     fn experiment_on_recursive(&self, name: &str, visited: &mut BTreeSet<String>) -> bool {
         if !visited.insert(name.to_string()) {
             panic!(
@@ -291,6 +378,26 @@ fn find_experiment(s: &[String], name: &str) -> Option<bool> {
     result
 }
 
+/// Returns the given experiment followed by the experiments it transitively inherits its
+/// default from.
+fn inheritance_chain(name: &str) -> Vec<String> {
+    let mut chain = vec![name.to_string()];
+    while let Some(DefaultValue::Inherited(other_name)) = EXPERIMENTS
+        .get(chain.last().expect("chain is not empty"))
+        .map(|exp| &exp.default)
+    {
+        if chain.contains(other_name) {
+            panic!(
+                "cyclic inheritance relation between experiments: `{} -> {}`",
+                chain.join(" -> "),
+                other_name
+            )
+        }
+        chain.push(other_name.clone());
+    }
+    chain
+}
+
 /// Gets the value of the env var for experiments.
 fn compiler_exp_var() -> Vec<String> {
     static EXP_VAR: Lazy<Vec<String>> = Lazy::new(|| {
@@ -308,6 +415,83 @@ fn compiler_exp_var() -> Vec<String> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::run_move_compiler_to_stderr;
+    use move_binary_format::file_format::Bytecode;
+    use move_compiler::compiled_unit::CompiledUnit;
+
+    #[test]
+    fn test_module_experiment_overrides() {
+        let dir = tempfile::tempdir().expect("temp dir can be created");
+        let source = dir.path().join("module_experiment_overrides.move");
+        std::fs::write(
+            &source,
+            "module 0x42::included { fun f(x: bool): u64 { if (!x) 1 else 2 } }
+             module 0x42::excluded { fun f(x: bool): u64 { if (!x) 1 else 2 } }",
+        )
+        .expect("source can be written");
+        // Without other optimizations, `!x` is compiled to `Not; BrFalse`, which the peephole
+        // optimizer turns into a single `BrTrue`. Turning on `OPTIMIZE` for a module only turns
+        // on the optimizations inheriting from it for that module.
+        let options = Options {
+            sources: vec![source.to_string_lossy().to_string()],
+            ..Options::default()
+        }
+        .set_experiment(Experiment::OPTIMIZE, false)
+        .set_module_experiment("0x42::included", Experiment::OPTIMIZE, true)
+        .set_module_experiment("0x42::included", Experiment::VARIABLE_COALESCING, false)
+        .set_module_experiment("0x42::included", Experiment::CFG_SIMPLIFICATION, false);
+        let (_, units) = run_move_compiler_to_stderr(options).expect("compilation succeeds");
+
+        let has_not: BTreeMap<String, bool> = units
+            .into_iter()
+            .filter_map(|unit| match unit.into_compiled_unit() {
+                CompiledUnit::Module(named_module) => Some((
+                    named_module.module.self_name().to_string(),
+                    named_module
+                        .module
+                        .function_defs
+                        .iter()
+                        .filter_map(|def| def.code.as_ref())
+                        .any(|code| code.code.contains(&Bytecode::Not)),
+                )),
+                CompiledUnit::Script(_) => None,
+            })
+            .collect();
+        assert_eq!(has_not.get("included"), Some(&false));
+        assert_eq!(has_not.get("excluded"), Some(&true));
+    }
+
+    #[test]
+    fn test_module_experiment_precedence() {
+        // A module override of `OPTIMIZE` applies to the experiments inheriting from it...
+        let options =
+            Options::default().set_module_experiment("0x42::m", Experiment::OPTIMIZE, false);
+        assert!(!options.experiment_on_in_module("0x42::m", Experiment::PEEPHOLE_OPTIMIZATION));
+        assert!(options.experiment_on_in_module("0x42::n", Experiment::PEEPHOLE_OPTIMIZATION));
+
+        // ...unless they are explicitly set globally, while a module override of the
+        // experiment itself still wins over its global setting.
+        let options = Options::default()
+            .set_experiment(Experiment::PEEPHOLE_OPTIMIZATION, true)
+            .set_experiment(Experiment::VARIABLE_COALESCING, true)
+            .set_module_experiment("0x42::m", Experiment::OPTIMIZE, false)
+            .set_module_experiment("0x42::m", Experiment::VARIABLE_COALESCING, false);
+        assert!(options.experiment_on_in_module("0x42::m", Experiment::PEEPHOLE_OPTIMIZATION));
+        assert!(!options.experiment_on_in_module("0x42::m", Experiment::CFG_SIMPLIFICATION));
+        assert!(!options.experiment_on_in_module("0x42::m", Experiment::VARIABLE_COALESCING));
+
+        // Passes are added to the pipeline if their experiment is on for some module.
+        let options =
+            Options::default().set_module_experiment("0x42::m", Experiment::COPY_PROPAGATION, true);
+        assert!(!options.experiment_on(Experiment::COPY_PROPAGATION));
+        assert!(options.experiment_on_in_any_module(Experiment::COPY_PROPAGATION));
+    }
+
+    #[test]
+    #[should_panic(expected = "is not consulted per module")]
+    fn test_module_experiment_not_consulted() {
+        Options::default().set_module_experiment("0x42::m", Experiment::INLINING, false);
+    }
 
     #[test]
     fn test_options_ref_cell_clone() {
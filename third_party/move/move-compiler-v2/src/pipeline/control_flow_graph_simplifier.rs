@@ -84,6 +84,7 @@
 //!
 //! Side effects: removes all annotations.
 
+use crate::{pipeline::experiment_on_for_function, Experiment};
 use itertools::Itertools;
 use move_model::model::FunctionEnv;
 use move_stackless_bytecode::{
@@ -106,7 +107,9 @@ impl FunctionTargetProcessor for ControlFlowGraphSimplifier {
         data: FunctionData,
         _scc_opt: Option<&[FunctionEnv]>,
     ) -> FunctionData {
-        if fun_env.is_native() {
+        if fun_env.is_native()
+            || !experiment_on_for_function(fun_env, Experiment::CFG_SIMPLIFICATION)
+        {
             return data;
         }
         let mut transformer = ControlFlowGraphSimplifierTransformation::new(data);
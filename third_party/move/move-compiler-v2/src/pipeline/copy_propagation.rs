@@ -24,7 +24,13 @@
 //! let d = a + 1;
 //! ```
 
-use crate::pipeline::avail_copies_analysis::{AvailCopies, AvailCopiesAnnotation};
+use crate::{
+    pipeline::{
+        avail_copies_analysis::{AvailCopies, AvailCopiesAnnotation},
+        experiment_on_for_function,
+    },
+    Experiment,
+};
 use move_binary_format::file_format::CodeOffset;
 use move_model::model::FunctionEnv;
 use move_stackless_bytecode::{
@@ -66,7 +72,9 @@ impl FunctionTargetProcessor for CopyPropagation {
         mut data: FunctionData,
         _scc_opt: Option<&[FunctionEnv]>,
     ) -> FunctionData {
-        if func_env.is_native() {
+        if func_env.is_native()
+            || !experiment_on_for_function(func_env, Experiment::COPY_PROPAGATION)
+        {
             return data;
         }
         let code = std::mem::take(&mut data.code);
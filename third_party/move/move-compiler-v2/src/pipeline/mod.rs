@@ -2,13 +2,19 @@
 // Parts of the project are originally copyright © Meta Platforms, Inc.
 // SPDX-License-Identifier: Apache-2.0
 
-use crate::pipeline::{
-    avail_copies_analysis::AvailCopiesAnalysisProcessor,
-    exit_state_analysis::ExitStateAnalysisProcessor, flush_writes_processor::FlushWritesProcessor,
-    livevar_analysis_processor::LiveVarAnalysisProcessor,
-    uninitialized_use_checker::UninitializedUseChecker,
-    unreachable_code_analysis::UnreachableCodeProcessor, variable_coalescing::VariableCoalescing,
+use crate::{
+    pipeline::{
+        avail_copies_analysis::AvailCopiesAnalysisProcessor,
+        exit_state_analysis::ExitStateAnalysisProcessor,
+        flush_writes_processor::FlushWritesProcessor,
+        livevar_analysis_processor::LiveVarAnalysisProcessor,
+        uninitialized_use_checker::UninitializedUseChecker,
+        unreachable_code_analysis::UnreachableCodeProcessor,
+        variable_coalescing::VariableCoalescing,
+    },
+    Options,
 };
+use move_model::model::FunctionEnv;
 use move_stackless_bytecode::function_target::FunctionTarget;
 
 pub mod ability_processor;
@@ -42,3 +48,14 @@ pub fn register_formatters(target: &FunctionTarget) {
     UnreachableCodeProcessor::register_formatters(target);
     VariableCoalescing::register_formatters(target);
 }
+
+/// Returns true if the experiment is on for the module of the function, taking per-module
+/// overrides into account (see `Options::experiment_on_in_module`).
+pub fn experiment_on_for_function(fun_env: &FunctionEnv, name: &str) -> bool {
+    fun_env
+        .module_env
+        .env
+        .get_extension::<Options>()
+        .unwrap_or_default()
+        .experiment_on_in_module(&fun_env.module_env.get_full_name_str(), name)
+}
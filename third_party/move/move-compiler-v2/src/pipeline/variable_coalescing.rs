@@ -32,7 +32,10 @@
 //! Note that this transformation could potentially create several dead stores, which
 //! can be removed by running the dead store elimination transformation afterwards.
 
-use crate::pipeline::livevar_analysis_processor::LiveVarAnnotation;
+use crate::{
+    pipeline::{experiment_on_for_function, livevar_analysis_processor::LiveVarAnnotation},
+    Experiment,
+};
 use move_binary_format::file_format::CodeOffset;
 use move_model::{ast::TempIndex, model::FunctionEnv, ty::Type};
 use move_stackless_bytecode::{
@@ -292,7 +295,9 @@ impl FunctionTargetProcessor for VariableCoalescing {
         mut data: FunctionData,
         _scc_opt: Option<&[FunctionEnv]>,
     ) -> FunctionData {
-        if func_env.is_native() {
+        if func_env.is_native()
+            || !experiment_on_for_function(func_env, Experiment::VARIABLE_COALESCING)
+        {
             return data;
         }
         let target = FunctionTarget::new(func_env, &data);
@@ -2,7 +2,9 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use once_cell::sync::Lazy;
-use prometheus::{register_histogram_vec, HistogramTimer, HistogramVec};
+use prometheus::{
+    register_histogram_vec, register_int_counter, HistogramTimer, HistogramVec, IntCounter,
+};
 
 /// Helper trait to encapsulate [HistogramVec] functionality. Users can use this trait to time
 /// different VM parts collecting metrics for different labels. Use wisely as timers do introduce
@@ -44,3 +46,13 @@ pub static VM_TIMER: Lazy<HistogramVec> = Lazy::new(|| {
     )
     .expect("Registering the histogram should always succeed")
 });
+
+/// Counts scripts that were not cached because their size exceeded the maximum size of a script
+/// that can be cached by the script cache.
+pub static NUM_UNCACHED_OVERSIZED_SCRIPTS: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "vm_num_uncached_oversized_scripts",
+        "Number of scripts which were too large to be cached"
+    )
+    .expect("Registering the counter should always succeed")
+});
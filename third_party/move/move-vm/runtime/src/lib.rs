@@ -38,7 +38,7 @@ pub use loader::{Function, LoadedFunction, Module, Script};
 #[cfg(any(test, feature = "testing"))]
 pub use storage::implementations::unreachable_code_storage;
 pub use storage::{
-    code_storage::{ambassador_impl_CodeStorage, CodeStorage},
+    code_storage::{ambassador_impl_CodeStorage, CodeStorage, ScriptMetadata},
     environment::{
         ambassador_impl_WithRuntimeEnvironment, RuntimeEnvironment, WithRuntimeEnvironment,
     },
@@ -3,7 +3,12 @@
 
 use crate::{loader::Script, ModuleStorage};
 use ambassador::delegatable_trait;
-use move_binary_format::{errors::VMResult, file_format::CompiledScript};
+use move_binary_format::{
+    access::ScriptAccess,
+    errors::VMResult,
+    file_format::{AbilitySet, CompiledScript, SignatureToken},
+};
+use move_vm_metrics::NUM_UNCACHED_OVERSIZED_SCRIPTS;
 use move_vm_types::{
     code::{Code, ScriptCache},
     sha3_256,
@@ -17,15 +22,46 @@ use std::sync::Arc;
 pub trait CodeStorage: ModuleStorage {
     /// Returns a deserialized script, either by directly deserializing it from the provided bytes
     /// (and caching it), or fetching it from the cache. Note that there are no guarantees that the
-    /// returned script is verified. An error is returned if the deserialization fails.
+    /// returned script is verified. An error is returned if the deserialization fails. Scripts
+    /// larger than the maximum cacheable size of the script cache are never cached.
     fn deserialize_and_cache_script(
         &self,
         serialized_script: &[u8],
     ) -> VMResult<Arc<CompiledScript>>;
 
-    /// Returns a verified script. If not yet cached, verified from scratch and cached. An error is
-    /// returned if script fails to deserialize or verify.
+    /// Returns a verified script. If not yet cached, verified from scratch and cached (unless it
+    /// is larger than the maximum cacheable size of the script cache). An error is returned if
+    /// script fails to deserialize or verify. If the script cache keeps track of scripts failing
+    /// (local) verification, such scripts are rejected with the recorded error without running
+    /// the verifier again. If the script cache is verifying the script in background, waits for
+    /// that verification to finish first, unless it has not started running yet.
     fn verify_and_cache_script(&self, serialized_script: &[u8]) -> VMResult<Arc<Script>>;
+
+    /// Returns the type parameters and the parameter types of a script, without verifying it. If
+    /// the script is cached, its deserialized form is reused, otherwise the script is deserialized
+    /// but not cached. An error is returned if the deserialization fails.
+    fn fetch_script_metadata(&self, serialized_script: &[u8]) -> VMResult<ScriptMetadata>;
+
+    /// Returns the hash under which external systems key the serialized script, computed by the
+    /// hasher of the script cache (SHA3-256 by default). Only meant for interoperability: scripts
+    /// are always cached by their SHA3-256 hash.
+    fn external_script_hash(&self, serialized_script: &[u8]) -> [u8; 32];
+}
+
+/// Signature of a script: the ability constraints of its type parameters and its parameter types.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ScriptMetadata {
+    pub type_parameters: Vec<AbilitySet>,
+    pub parameters: Vec<SignatureToken>,
+}
+
+impl ScriptMetadata {
+    fn new(script: &CompiledScript) -> Self {
+        Self {
+            type_parameters: script.type_parameters.clone(),
+            parameters: script.signature_at(script.parameters).0.clone(),
+        }
+    }
 }
 
 impl<T> CodeStorage for T
@@ -44,7 +80,11 @@ where
                 let deserialized_script = self
                     .runtime_environment()
                     .deserialize_into_script(serialized_script)?;
-                self.insert_deserialized_script(hash, deserialized_script)
+                if is_too_large_to_cache(self, hash, serialized_script) {
+                    Arc::new(deserialized_script)
+                } else {
+                    self.insert_deserialized_script(hash, deserialized_script)
+                }
             },
         })
     }
@@ -53,6 +93,9 @@ where
         use Code::*;
 
         let hash = sha3_256(serialized_script);
+        // If the script is being verified in background, wait for the result instead of
+        // verifying the script again.
+        self.wait_for_background_verification(&hash);
         let deserialized_script = match self.get_script(&hash) {
             Some(Verified(script)) => return Ok(script),
             Some(Deserialized(deserialized_script)) => deserialized_script,
@@ -62,10 +105,20 @@ where
                 .map(Arc::new)?,
         };
 
-        // Locally verify the script.
+        if let Some(error) = self.get_known_bad_script(&hash) {
+            return Err(error);
+        }
+
+        // Locally verify the script. Only errors from local verification are remembered: they
+        // depend solely on the script, while linking errors may go away when dependencies are
+        // upgraded.
         let locally_verified_script = self
             .runtime_environment()
-            .build_locally_verified_script(deserialized_script)?;
+            .build_locally_verified_script(deserialized_script)
+            .map_err(|error| {
+                self.insert_known_bad_script(hash, error.clone());
+                error
+            })?;
 
         // Verify the script is correct w.r.t. its dependencies.
         let immediate_dependencies = locally_verified_script
@@ -79,6 +132,42 @@ where
             .runtime_environment()
             .build_verified_script(locally_verified_script, &immediate_dependencies)?;
 
-        Ok(self.insert_verified_script(hash, verified_script))
+        Ok(if is_too_large_to_cache(self, hash, serialized_script) {
+            Arc::new(verified_script)
+        } else {
+            self.insert_verified_script(hash, verified_script)
+        })
+    }
+
+    fn fetch_script_metadata(&self, serialized_script: &[u8]) -> VMResult<ScriptMetadata> {
+        let hash = sha3_256(serialized_script);
+        Ok(match self.get_script(&hash) {
+            Some(script) => ScriptMetadata::new(script.deserialized()),
+            None => ScriptMetadata::new(
+                &self
+                    .runtime_environment()
+                    .deserialize_into_script(serialized_script)?,
+            ),
+        })
+    }
+
+    fn external_script_hash(&self, serialized_script: &[u8]) -> [u8; 32] {
+        self.script_hasher().hash(serialized_script)
+    }
+}
+
+/// Returns true if the serialized script exceeds the maximum size of a script that can be cached.
+/// Every such script is recorded in metrics once, even if it is fetched multiple times.
+fn is_too_large_to_cache(
+    script_cache: &impl ScriptCache<Key = [u8; 32]>,
+    hash: [u8; 32],
+    serialized_script: &[u8],
+) -> bool {
+    let too_large = script_cache
+        .max_script_bytes()
+        .is_some_and(|max_script_bytes| serialized_script.len() > max_script_bytes);
+    if too_large && script_cache.insert_oversized_script(hash) {
+        NUM_UNCACHED_OVERSIZED_SCRIPTS.inc();
     }
+    too_large
 }
@@ -28,6 +28,8 @@ use move_core_types::{
 use move_vm_metrics::{Timer, VM_TIMER};
 #[cfg(any(test, feature = "testing"))]
 use move_vm_types::loaded_data::runtime_types::{StructIdentifier, StructNameIndex};
+#[cfg(any(test, feature = "testing"))]
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 
 /// [MoveVM] runtime environment encapsulating different configurations. Shared between the VM and
@@ -72,6 +74,12 @@ pub struct RuntimeEnvironment {
     //   Provide a generic (trait) implementation for clients to implement their own type caching
     //   logic.
     ty_cache: StructInfoCache,
+
+    /// Number of scripts passed to the bytecode verifier by
+    /// [RuntimeEnvironment::build_locally_verified_script], so that tests can check when the
+    /// verification of a script is skipped.
+    #[cfg(any(test, feature = "testing"))]
+    num_locally_verified_scripts: AtomicUsize,
 }
 
 impl RuntimeEnvironment {
@@ -101,6 +109,8 @@ impl RuntimeEnvironment {
             natives,
             struct_name_index_map: Arc::new(StructNameIndexMap::empty()),
             ty_cache: StructInfoCache::empty(),
+            #[cfg(any(test, feature = "testing"))]
+            num_locally_verified_scripts: AtomicUsize::new(0),
         }
     }
 
@@ -121,6 +131,9 @@ impl RuntimeEnvironment {
         &self,
         compiled_script: Arc<CompiledScript>,
     ) -> VMResult<LocallyVerifiedScript> {
+        #[cfg(any(test, feature = "testing"))]
+        self.num_locally_verified_scripts
+            .fetch_add(1, Ordering::Relaxed);
         move_bytecode_verifier::verify_script_with_config(
             &self.vm_config().verifier_config,
             compiled_script.as_ref(),
@@ -304,6 +317,13 @@ impl RuntimeEnvironment {
     ) -> PartialVMResult<StructIdentifier> {
         self.struct_name_index_map.idx_to_struct_name(idx)
     }
+
+    /// Test-only function returning the number of scripts run through the bytecode verifier with
+    /// this environment.
+    #[cfg(any(test, feature = "testing"))]
+    pub fn num_locally_verified_scripts_for_test(&self) -> usize {
+        self.num_locally_verified_scripts.load(Ordering::Relaxed)
+    }
 }
 
 impl Clone for RuntimeEnvironment {
@@ -315,6 +335,8 @@ impl Clone for RuntimeEnvironment {
             natives: self.natives.clone(),
             struct_name_index_map: self.struct_name_index_map.clone(),
             ty_cache: self.ty_cache.clone(),
+            #[cfg(any(test, feature = "testing"))]
+            num_locally_verified_scripts: AtomicUsize::new(0),
         }
     }
 }
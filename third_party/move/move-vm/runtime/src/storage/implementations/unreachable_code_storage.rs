@@ -2,7 +2,8 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use crate::{
-    CodeStorage, Module, ModuleStorage, RuntimeEnvironment, Script, WithRuntimeEnvironment,
+    CodeStorage, Module, ModuleStorage, RuntimeEnvironment, Script, ScriptMetadata,
+    WithRuntimeEnvironment,
 };
 use bytes::Bytes;
 use move_binary_format::{errors::VMResult, file_format::CompiledScript, CompiledModule};
@@ -80,4 +81,12 @@ impl CodeStorage for UnreachableCodeStorage {
     fn verify_and_cache_script(&self, _serialized_script: &[u8]) -> VMResult<Arc<Script>> {
         unreachable!()
     }
+
+    fn fetch_script_metadata(&self, _serialized_script: &[u8]) -> VMResult<ScriptMetadata> {
+        unreachable!()
+    }
+
+    fn external_script_hash(&self, _serialized_script: &[u8]) -> [u8; 32] {
+        unreachable!()
+    }
 }
@@ -4,7 +4,7 @@
 use crate::{
     loader::{Function, Module, Script},
     storage::{
-        code_storage::{ambassador_impl_CodeStorage, CodeStorage},
+        code_storage::{ambassador_impl_CodeStorage, CodeStorage, ScriptMetadata},
         environment::{
             ambassador_impl_WithRuntimeEnvironment, RuntimeEnvironment, WithRuntimeEnvironment,
         },
@@ -15,7 +15,7 @@ use crate::{
 use ambassador::Delegate;
 use bytes::Bytes;
 use move_binary_format::{
-    errors::{PartialVMResult, VMResult},
+    errors::{PartialVMResult, VMError, VMResult},
     file_format::CompiledScript,
     CompiledModule,
 };
@@ -24,7 +24,10 @@ use move_core_types::{
     metadata::Metadata,
 };
 use move_vm_types::{
-    code::{ambassador_impl_ScriptCache, Code, ModuleBytesStorage, ScriptCache, UnsyncScriptCache},
+    code::{
+        ambassador_impl_ScriptCache, Code, ModuleBytesStorage, ScriptCache, ScriptCacheConfig,
+        ScriptHasher, UnsyncScriptCache,
+    },
     loaded_data::runtime_types::{StructType, Type},
 };
 use std::sync::Arc;
@@ -37,6 +40,69 @@ use std::sync::Arc;
 pub struct UnsyncCodeStorage<M>(UnsyncCodeStorageImpl<M>);
 
 impl<M: ModuleStorage> UnsyncCodeStorage<M> {
+    /// Returns the same code storage, but with the script cache bounded by the given
+    /// configuration (e.g., [ScriptCacheConfig::mainnet]). Other settings of the script cache,
+    /// such as its hasher, as well as already cached scripts are kept, as long as they fit. The
+    /// number of shards is ignored because the script cache of this storage is not sharded.
+    pub fn with_script_cache_config(self, config: &ScriptCacheConfig) -> Self {
+        let UnsyncCodeStorageImpl {
+            script_cache,
+            module_storage,
+        } = self.0;
+        Self(UnsyncCodeStorageImpl {
+            script_cache: script_cache
+                .with_max_num_scripts(config.max_num_scripts)
+                .with_max_script_bytes(config.max_script_bytes)
+                .with_max_known_bad_scripts(config.max_known_bad_scripts),
+            module_storage,
+        })
+    }
+
+    /// Returns the same code storage, but never caching scripts larger than the specified number
+    /// of bytes. Such scripts are still deserialized and verified on every access.
+    pub fn with_max_script_bytes(self, max_script_bytes: usize) -> Self {
+        let UnsyncCodeStorageImpl {
+            script_cache,
+            module_storage,
+        } = self.0;
+        Self(UnsyncCodeStorageImpl {
+            script_cache: script_cache.with_max_script_bytes(max_script_bytes),
+            module_storage,
+        })
+    }
+
+    /// Returns the same code storage, but remembering up to the specified number of scripts that
+    /// failed verification, so that they are not verified again.
+    pub fn with_max_known_bad_scripts(self, max_known_bad_scripts: usize) -> Self {
+        let UnsyncCodeStorageImpl {
+            script_cache,
+            module_storage,
+        } = self.0;
+        Self(UnsyncCodeStorageImpl {
+            script_cache: script_cache.with_max_known_bad_scripts(max_known_bad_scripts),
+            module_storage,
+        })
+    }
+
+    /// Returns the same code storage, but computing the external hashes of scripts (see
+    /// [CodeStorage::external_script_hash]) with the given hasher instead of SHA3-256. Scripts
+    /// are still cached by their SHA3-256 hash.
+    pub fn with_script_hasher(self, script_hasher: Arc<dyn ScriptHasher>) -> Self {
+        let UnsyncCodeStorageImpl {
+            script_cache,
+            module_storage,
+        } = self.0;
+        Self(UnsyncCodeStorageImpl {
+            script_cache: script_cache.with_script_hasher(script_hasher),
+            module_storage,
+        })
+    }
+
+    /// Returns true if the script with the given hash is known to fail verification.
+    pub fn is_known_bad(&self, script_hash: &[u8; 32]) -> bool {
+        self.0.is_known_bad(script_hash)
+    }
+
     /// Returns the reference to the underlying module storage used by this code storage.
     pub fn module_storage(&self) -> &M {
         &self.0.module_storage
@@ -129,13 +195,19 @@ mod test {
     use crate::storage::{
         code_storage::CodeStorage, implementations::unsync_module_storage::test::add_module_bytes,
     };
-    use claims::assert_ok;
+    use claims::{assert_err, assert_ok};
     use move_binary_format::{
-        file_format::empty_script_with_dependencies, file_format_common::VERSION_DEFAULT,
+        file_format::{
+            empty_script_with_dependencies, AbilitySet, Bytecode, Signature, SignatureIndex,
+            SignatureToken,
+        },
+        file_format_common::VERSION_DEFAULT,
+    };
+    use move_core_types::{
+        identifier::Identifier, language_storage::ModuleId, vm_status::StatusCode,
     };
-    use move_core_types::{identifier::Identifier, language_storage::ModuleId};
     use move_vm_test_utils::InMemoryStorage;
-    use move_vm_types::sha3_256;
+    use move_vm_types::{code::SyncScriptCache, sha3_256};
 
     fn make_script<'a>(dependencies: impl IntoIterator<Item = &'a str>) -> Vec<u8> {
         let mut script = empty_script_with_dependencies(dependencies);
@@ -200,4 +272,310 @@ mod test {
             .assert_cached_state(vec![], vec![&a_id, &b_id, &c_id]);
         code_storage.assert_cached_state(vec![], vec![&hash]);
     }
+
+    #[test]
+    fn test_oversized_scripts_are_not_cached() {
+        let mut module_bytes_storage = InMemoryStorage::new();
+        add_module_bytes(&mut module_bytes_storage, "a", vec![], vec![]);
+        add_module_bytes(&mut module_bytes_storage, "b", vec![], vec![]);
+        add_module_bytes(&mut module_bytes_storage, "c", vec![], vec![]);
+
+        let small_serialized_script = make_script(vec!["a"]);
+        let small_hash = sha3_256(&small_serialized_script);
+        let large_serialized_scripts = [make_script(vec!["a", "b"]), make_script(vec!["a", "c"])];
+        for large_serialized_script in &large_serialized_scripts {
+            assert!(large_serialized_script.len() > small_serialized_script.len());
+        }
+
+        let runtime_environment = RuntimeEnvironment::new(vec![]);
+        let code_storage = module_bytes_storage
+            .into_unsync_code_storage(runtime_environment)
+            .with_max_script_bytes(small_serialized_script.len());
+
+        assert_ok!(code_storage.deserialize_and_cache_script(&small_serialized_script));
+        for large_serialized_script in &large_serialized_scripts {
+            assert_ok!(code_storage.deserialize_and_cache_script(large_serialized_script));
+        }
+        code_storage.assert_cached_state(vec![&small_hash], vec![]);
+
+        // Only the small script is a cache hit when fetched again.
+        assert_ok!(code_storage.verify_and_cache_script(&small_serialized_script));
+        for large_serialized_script in &large_serialized_scripts {
+            assert_ok!(code_storage.verify_and_cache_script(large_serialized_script));
+            let large_hash = sha3_256(large_serialized_script);
+            assert!(code_storage.0.get_script(&large_hash).is_none());
+        }
+        code_storage.assert_cached_state(vec![], vec![&small_hash]);
+
+        // Each oversized script is counted once, even though it was fetched twice.
+        assert_eq!(code_storage.0.num_oversized_scripts(), 2);
+    }
+
+    #[test]
+    fn test_fetch_script_metadata() {
+        let mut module_bytes_storage = InMemoryStorage::new();
+        add_module_bytes(&mut module_bytes_storage, "a", vec![], vec![]);
+
+        let runtime_environment = RuntimeEnvironment::new(vec![]);
+        let code_storage = module_bytes_storage.into_unsync_code_storage(runtime_environment);
+
+        let mut script = empty_script_with_dependencies(vec!["a"]);
+        script.version = VERSION_DEFAULT;
+        script.type_parameters = vec![AbilitySet::PRIMITIVES];
+        script.signatures.push(Signature(vec![
+            SignatureToken::U64,
+            SignatureToken::Address,
+        ]));
+        script.parameters = SignatureIndex((script.signatures.len() - 1) as u16);
+        let mut serialized_script = vec![];
+        assert_ok!(script.serialize(&mut serialized_script));
+        let hash = sha3_256(&serialized_script);
+
+        let expected = ScriptMetadata {
+            type_parameters: vec![AbilitySet::PRIMITIVES],
+            parameters: vec![SignatureToken::U64, SignatureToken::Address],
+        };
+
+        // Metadata is returned without caching or verifying the script.
+        assert_eq!(
+            assert_ok!(code_storage.fetch_script_metadata(&serialized_script)),
+            expected
+        );
+        code_storage.assert_cached_state(vec![], vec![]);
+
+        // Cached scripts are reused.
+        assert_ok!(code_storage.deserialize_and_cache_script(&serialized_script));
+        assert_eq!(
+            assert_ok!(code_storage.fetch_script_metadata(&serialized_script)),
+            expected
+        );
+        code_storage.assert_cached_state(vec![&hash], vec![]);
+
+        assert_err!(code_storage.fetch_script_metadata(&[0, 1, 2]));
+    }
+
+    /// Uses the length of the script as its hash.
+    struct LengthHasher;
+
+    impl ScriptHasher for LengthHasher {
+        fn hash(&self, bytes: &[u8]) -> [u8; 32] {
+            let mut hash = [0; 32];
+            hash[..8].copy_from_slice(&(bytes.len() as u64).to_le_bytes());
+            hash
+        }
+    }
+
+    #[test]
+    fn test_script_hasher() {
+        let mut module_bytes_storage = InMemoryStorage::new();
+        add_module_bytes(&mut module_bytes_storage, "a", vec![], vec![]);
+        let serialized_script = make_script(vec!["a"]);
+
+        // By default, the external hash is the SHA3-256 hash scripts are cached by.
+        let code_storage = module_bytes_storage
+            .clone()
+            .into_unsync_code_storage(RuntimeEnvironment::new(vec![]));
+        let hash = sha3_256(&serialized_script);
+        assert_eq!(code_storage.external_script_hash(&serialized_script), hash);
+        assert_ok!(code_storage.deserialize_and_cache_script(&serialized_script));
+        code_storage.assert_cached_state(vec![&hash], vec![]);
+
+        // A custom hasher only changes the external hash, scripts are still cached by their
+        // SHA3-256 hash.
+        let code_storage = module_bytes_storage
+            .into_unsync_code_storage(RuntimeEnvironment::new(vec![]))
+            .with_script_hasher(Arc::new(LengthHasher));
+        assert_eq!(
+            code_storage.external_script_hash(&serialized_script),
+            LengthHasher.hash(&serialized_script)
+        );
+        assert_ne!(LengthHasher.hash(&serialized_script), hash);
+        assert_ok!(code_storage.deserialize_and_cache_script(&serialized_script));
+        code_storage.assert_cached_state(vec![&hash], vec![]);
+        assert_ok!(code_storage.verify_and_cache_script(&serialized_script));
+        code_storage.assert_cached_state(vec![], vec![&hash]);
+    }
+
+    fn make_bad_script(num_pops: usize) -> Vec<u8> {
+        // Pops from an empty stack, which fails local verification.
+        let mut script = empty_script_with_dependencies(vec!["a"]);
+        script.version = VERSION_DEFAULT;
+        script.code.code = vec![Bytecode::Pop; num_pops];
+        script.code.code.push(Bytecode::Ret);
+
+        let mut serialized_script = vec![];
+        assert_ok!(script.serialize(&mut serialized_script));
+        serialized_script
+    }
+
+    #[test]
+    fn test_script_cache_config_keeps_script_cache_settings() {
+        let mut module_bytes_storage = InMemoryStorage::new();
+        add_module_bytes(&mut module_bytes_storage, "a", vec![], vec![]);
+
+        let code_storage = module_bytes_storage
+            .into_unsync_code_storage(RuntimeEnvironment::new(vec![]))
+            .with_script_hasher(Arc::new(LengthHasher))
+            .with_max_known_bad_scripts(1);
+        let serialized_bad_script = make_bad_script(1);
+        let bad_hash = sha3_256(&serialized_bad_script);
+        assert_err!(code_storage.verify_and_cache_script(&serialized_bad_script));
+        assert!(code_storage.is_known_bad(&bad_hash));
+
+        let code_storage = code_storage.with_script_cache_config(&ScriptCacheConfig::local());
+        assert!(code_storage.is_known_bad(&bad_hash));
+
+        let serialized_script = make_script(vec!["a"]);
+        assert_eq!(
+            code_storage.external_script_hash(&serialized_script),
+            LengthHasher.hash(&serialized_script)
+        );
+        let hash = sha3_256(&serialized_script);
+        assert_ok!(code_storage.verify_and_cache_script(&serialized_script));
+        code_storage.assert_cached_state(vec![], vec![&hash]);
+    }
+
+    #[test]
+    fn test_known_bad_scripts_are_not_verified_again() {
+        let mut module_bytes_storage = InMemoryStorage::new();
+        add_module_bytes(&mut module_bytes_storage, "a", vec![], vec![]);
+
+        let runtime_environment = RuntimeEnvironment::new(vec![]);
+        let code_storage = module_bytes_storage
+            .into_unsync_code_storage(runtime_environment)
+            .with_max_known_bad_scripts(1);
+        let num_verified = || {
+            code_storage
+                .runtime_environment()
+                .num_locally_verified_scripts_for_test()
+        };
+
+        let serialized_bad_script_1 = make_bad_script(1);
+        let bad_hash_1 = sha3_256(&serialized_bad_script_1);
+        let serialized_bad_script_2 = make_bad_script(2);
+        let bad_hash_2 = sha3_256(&serialized_bad_script_2);
+
+        let error = assert_err!(code_storage.verify_and_cache_script(&serialized_bad_script_1));
+        assert_eq!(
+            error.major_status(),
+            StatusCode::NEGATIVE_STACK_SIZE_WITHIN_BLOCK
+        );
+        assert_eq!(num_verified(), 1);
+        assert!(code_storage.is_known_bad(&bad_hash_1));
+
+        // The recorded error is returned without running the verifier again.
+        assert_eq!(
+            assert_err!(code_storage.verify_and_cache_script(&serialized_bad_script_1)),
+            error
+        );
+        assert_eq!(num_verified(), 1);
+        code_storage.assert_cached_state(vec![], vec![]);
+
+        // At most one known bad script is remembered, so the first one is verified again once the
+        // second one is recorded.
+        assert_err!(code_storage.verify_and_cache_script(&serialized_bad_script_2));
+        assert_eq!(num_verified(), 2);
+        assert!(code_storage.is_known_bad(&bad_hash_2));
+        assert!(!code_storage.is_known_bad(&bad_hash_1));
+        assert_eq!(
+            assert_err!(code_storage.verify_and_cache_script(&serialized_bad_script_1)),
+            error
+        );
+        assert_eq!(num_verified(), 3);
+
+        // Valid scripts are verified once, and then cached.
+        let serialized_script = make_script(vec!["a"]);
+        assert_ok!(code_storage.verify_and_cache_script(&serialized_script));
+        assert_ok!(code_storage.verify_and_cache_script(&serialized_script));
+        assert_eq!(num_verified(), 4);
+    }
+
+    /// Code storage with a [SyncScriptCache], which can verify scripts in background.
+    #[derive(Delegate)]
+    #[delegate(
+        WithRuntimeEnvironment,
+        target = "module_storage",
+        where = "M: ModuleStorage"
+    )]
+    #[delegate(ModuleStorage, target = "module_storage", where = "M: ModuleStorage")]
+    struct SyncCodeStorage<M> {
+        script_cache: Arc<SyncScriptCache<[u8; 32], CompiledScript, Script>>,
+        module_storage: M,
+    }
+
+    impl<M> ScriptCache for SyncCodeStorage<M> {
+        type Deserialized = CompiledScript;
+        type Key = [u8; 32];
+        type Verified = Script;
+
+        fn insert_deserialized_script(
+            &self,
+            key: Self::Key,
+            deserialized_script: Self::Deserialized,
+        ) -> Arc<Self::Deserialized> {
+            self.script_cache
+                .insert_deserialized_script(key, deserialized_script)
+        }
+
+        fn insert_verified_script(
+            &self,
+            key: Self::Key,
+            verified_script: Self::Verified,
+        ) -> Arc<Self::Verified> {
+            self.script_cache
+                .insert_verified_script(key, verified_script)
+        }
+
+        fn get_script(&self, key: &Self::Key) -> Option<Code<Self::Deserialized, Self::Verified>> {
+            self.script_cache.get_script(key)
+        }
+
+        fn num_scripts(&self) -> usize {
+            self.script_cache.num_scripts()
+        }
+
+        fn wait_for_background_verification(&self, key: &Self::Key) {
+            self.script_cache.wait_for_background_verification(key)
+        }
+    }
+
+    #[test]
+    fn test_unfinished_background_verification_does_not_block() {
+        let mut module_bytes_storage = InMemoryStorage::new();
+        add_module_bytes(&mut module_bytes_storage, "a", vec![], vec![]);
+
+        let runtime_environment = RuntimeEnvironment::new(vec![]);
+        let code_storage = SyncCodeStorage {
+            script_cache: Arc::new(SyncScriptCache::empty()),
+            module_storage: module_bytes_storage.into_unsync_module_storage(runtime_environment),
+        };
+        let verify: Arc<dyn Fn(Arc<CompiledScript>) -> VMResult<Script> + Send + Sync> =
+            Arc::new(|_| panic!("Background verification panics"));
+
+        // The task is dropped without being run.
+        let serialized_script_1 = make_script(vec![]);
+        let hash_1 = sha3_256(&serialized_script_1);
+        assert_ok!(code_storage.deserialize_and_cache_script(&serialized_script_1));
+        assert!(code_storage
+            .script_cache
+            .verify_in_background(&hash_1, verify.clone(), drop));
+        assert_ok!(code_storage.verify_and_cache_script(&serialized_script_1));
+
+        // The task panics while verifying the script.
+        let serialized_script_2 = make_script(vec!["a"]);
+        let hash_2 = sha3_256(&serialized_script_2);
+        assert_ok!(code_storage.deserialize_and_cache_script(&serialized_script_2));
+        assert!(code_storage
+            .script_cache
+            .verify_in_background(&hash_2, verify.clone(), |task| {
+                assert_err!(std::panic::catch_unwind(std::panic::AssertUnwindSafe(task)));
+            }));
+        assert_ok!(code_storage.verify_and_cache_script(&serialized_script_2));
+
+        // Both scripts have been verified in the foreground instead.
+        for hash in [&hash_1, &hash_2] {
+            let script = claims::assert_some!(code_storage.get_script(hash));
+            assert!(script.is_verified());
+        }
+    }
 }
@@ -1,12 +1,84 @@
 // Copyright (c) The Move Contributors
 // SPDX-License-Identifier: Apache-2.0
 
-use crate::code::Code;
+use crate::{code::Code, sha3_256};
 use ambassador::delegatable_trait;
 use crossbeam::utils::CachePadded;
 use dashmap::DashMap;
 use hashbrown::HashMap;
-use std::{cell::RefCell, hash::Hash, ops::Deref, sync::Arc};
+use move_binary_format::errors::{Location, PartialVMError, VMError, VMResult};
+use move_core_types::vm_status::StatusCode;
+use std::{
+    cell::RefCell,
+    collections::VecDeque,
+    hash::Hash,
+    ops::Deref,
+    sync::{Arc, Condvar, Mutex},
+};
+
+/// Computes the hashes under which external systems key serialized scripts. The VM itself always
+/// caches scripts by their SHA3-256 hash, whatever the hasher.
+pub trait ScriptHasher: Send + Sync {
+    fn hash(&self, bytes: &[u8]) -> [u8; 32];
+}
+
+/// Default [ScriptHasher], which hashes scripts with SHA3-256 (see [sha3_256]).
+pub struct Sha3_256ScriptHasher;
+
+impl ScriptHasher for Sha3_256ScriptHasher {
+    fn hash(&self, bytes: &[u8]) -> [u8; 32] {
+        sha3_256(bytes)
+    }
+}
+
+/// Configuration of the bounds of a script cache. Networks have very different script diversity,
+/// so presets are provided for mainnet, testnet and local nodes.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ScriptCacheConfig {
+    /// Maximum number of cached scripts. Once reached, the oldest cached scripts are evicted to
+    /// make space for new ones.
+    pub max_num_scripts: usize,
+    /// Maximum number of remembered scripts that failed verification.
+    pub max_known_bad_scripts: usize,
+    /// Maximum size (in bytes) of a serialized script that can be cached.
+    pub max_script_bytes: usize,
+    /// Number of shards of a [SyncScriptCache], must be a power of two greater than 1. Ignored by
+    /// [UnsyncScriptCache], which is not sharded.
+    pub num_shards: usize,
+}
+
+impl ScriptCacheConfig {
+    /// Configuration for mainnet nodes, where many distinct scripts are executed.
+    pub fn mainnet() -> Self {
+        Self {
+            max_num_scripts: 100_000,
+            max_known_bad_scripts: 10_000,
+            max_script_bytes: 64 * 1024,
+            num_shards: 256,
+        }
+    }
+
+    /// Configuration for testnet nodes.
+    pub fn testnet() -> Self {
+        Self {
+            max_num_scripts: 10_000,
+            max_known_bad_scripts: 1_000,
+            max_script_bytes: 64 * 1024,
+            num_shards: 64,
+        }
+    }
+
+    /// Configuration for local nodes (e.g., for development), where few scripts are executed but
+    /// they can be large.
+    pub fn local() -> Self {
+        Self {
+            max_num_scripts: 1_000,
+            max_known_bad_scripts: 100,
+            max_script_bytes: 1024 * 1024,
+            num_shards: 8,
+        }
+    }
+}
 
 /// Interface used by any script cache implementation.
 #[delegatable_trait]
@@ -38,11 +110,185 @@ pub trait ScriptCache {
 
     /// Returns the number of scripts stored in cache.
     fn num_scripts(&self) -> usize;
+
+    /// Returns the maximum size (in bytes) of a serialized script that can be cached, if there is
+    /// one. Larger scripts can still be deserialized and verified, but are never cached.
+    fn max_script_bytes(&self) -> Option<usize> {
+        None
+    }
+
+    /// Records that the script associated with the key was not cached because it is too large.
+    /// Returns true if the script has not been recorded before, so that every oversized script
+    /// is counted once. Caches that do not keep track of such scripts always return false.
+    fn insert_oversized_script(&self, _key: Self::Key) -> bool {
+        false
+    }
+
+    /// Returns the number of remembered scripts which were too large to be cached.
+    fn num_oversized_scripts(&self) -> usize {
+        0
+    }
+
+    /// Records that the script associated with the key failed verification with the given error,
+    /// so that it can be rejected without being verified again. Caches that do not keep track of
+    /// such scripts ignore the call.
+    ///
+    /// Verification errors depend on the verifier configuration, so a cache remembering them must
+    /// not outlive the runtime environment they were recorded with: when the configuration
+    /// changes, the cache has to be dropped together with its verified scripts.
+    fn insert_known_bad_script(&self, _key: Self::Key, _error: VMError) {}
+
+    /// Returns the verification error of the script associated with the key if the script is
+    /// known to fail verification, or [None] otherwise.
+    fn get_known_bad_script(&self, _key: &Self::Key) -> Option<VMError> {
+        None
+    }
+
+    /// Returns true if the script associated with the key is known to fail verification.
+    fn is_known_bad(&self, key: &Self::Key) -> bool {
+        self.get_known_bad_script(key).is_some()
+    }
+
+    /// Returns the hasher computing the hashes external systems key serialized scripts with, by
+    /// default SHA3-256. It is not used for the keys of the cache.
+    fn script_hasher(&self) -> &dyn ScriptHasher {
+        &Sha3_256ScriptHasher
+    }
+
+    /// Blocks until the background verification of the script associated with the key finishes,
+    /// if there is one running. Returns immediately if the verification task has not started yet,
+    /// so that the caller verifies the script itself instead of waiting for a task which may be
+    /// queued behind it (e.g., on the same thread pool). Caches that do not verify scripts in
+    /// background return immediately.
+    fn wait_for_background_verification(&self, _key: &Self::Key) {}
+}
+
+/// Bounded record of scripts (e.g., verification errors of scripts that failed verification, or
+/// scripts too large to be cached). Once full, the oldest entries are evicted first.
+struct ScriptRecords<K, T> {
+    capacity: usize,
+    records: HashMap<K, T>,
+    insertion_order: VecDeque<K>,
+}
+
+/// Verification errors of scripts that failed verification.
+type KnownBadScripts<K> = ScriptRecords<K, VMError>;
+
+/// Scripts which were not cached because they are too large.
+type OversizedScripts<K> = ScriptRecords<K, ()>;
+
+impl<K, T> ScriptRecords<K, T>
+where
+    K: Eq + Hash + Clone,
+    T: Clone,
+{
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            records: HashMap::new(),
+            insertion_order: VecDeque::new(),
+        }
+    }
+
+    /// Returns true if the record has been inserted, and false if the key is already recorded or
+    /// nothing can be recorded.
+    fn insert(&mut self, key: K, record: T) -> bool {
+        if self.capacity == 0 || self.records.contains_key(&key) {
+            return false;
+        }
+        if self.records.len() == self.capacity {
+            if let Some(oldest_key) = self.insertion_order.pop_front() {
+                self.records.remove(&oldest_key);
+            }
+        }
+        self.insertion_order.push_back(key.clone());
+        self.records.insert(key, record);
+        true
+    }
+
+    fn get(&self, key: &K) -> Option<T> {
+        self.records.get(key).cloned()
+    }
+
+    fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    /// Changes the capacity, evicting the oldest entries if there are more than the new capacity.
+    fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        while self.insertion_order.len() > capacity {
+            if let Some(oldest_key) = self.insertion_order.pop_front() {
+                self.records.remove(&oldest_key);
+            }
+        }
+    }
+}
+
+/// Progress of a background verification.
+#[derive(Clone, Copy, Default, Eq, PartialEq)]
+enum VerificationState {
+    /// The task has been spawned but is not running yet.
+    #[default]
+    Pending,
+    Running,
+    Finished,
+}
+
+/// Verification of a script running in background, which others can wait for.
+#[derive(Default)]
+struct InFlightVerification {
+    state: Mutex<VerificationState>,
+    state_cvar: Condvar,
+}
+
+impl InFlightVerification {
+    /// Waits for the verification to finish if it is running. Does not wait for a pending one,
+    /// whose task may never get to run while the caller is blocked.
+    fn wait(&self) {
+        let mut state = self
+            .state
+            .lock()
+            .expect("In-flight verification lock should not be poisoned");
+        while *state == VerificationState::Running {
+            state = self
+                .state_cvar
+                .wait(state)
+                .expect("In-flight verification lock should not be poisoned");
+        }
+    }
+
+    fn start(&self) {
+        let mut state = self
+            .state
+            .lock()
+            .expect("In-flight verification lock should not be poisoned");
+        if *state == VerificationState::Pending {
+            *state = VerificationState::Running;
+        }
+    }
+
+    fn finish(&self) {
+        *self
+            .state
+            .lock()
+            .expect("In-flight verification lock should not be poisoned") =
+            VerificationState::Finished;
+        self.state_cvar.notify_all();
+    }
 }
 
 /// Non-[Sync] implementation of script cache suitable for single-threaded execution.
 pub struct UnsyncScriptCache<K, D, V> {
     script_cache: RefCell<HashMap<K, Code<D, V>>>,
+    /// Keys of cached scripts, from the oldest to the newest, used for eviction.
+    insertion_order: RefCell<VecDeque<K>>,
+    max_num_scripts: Option<usize>,
+    max_script_bytes: Option<usize>,
+    /// Oversized scripts, remembered up to the maximum number of scripts.
+    oversized_scripts: RefCell<OversizedScripts<K>>,
+    known_bad_scripts: Option<RefCell<KnownBadScripts<K>>>,
+    script_hasher: Arc<dyn ScriptHasher>,
 }
 
 impl<K, D, V> UnsyncScriptCache<K, D, V>
@@ -54,7 +300,87 @@ where
     pub fn empty() -> Self {
         Self {
             script_cache: RefCell::new(HashMap::new()),
+            insertion_order: RefCell::new(VecDeque::new()),
+            max_num_scripts: None,
+            max_script_bytes: None,
+            oversized_scripts: RefCell::new(OversizedScripts::new(usize::MAX)),
+            known_bad_scripts: None,
+            script_hasher: Arc::new(Sha3_256ScriptHasher),
+        }
+    }
+
+    /// Returns an empty script cache with the bounds of the given configuration. The number of
+    /// shards is ignored because this cache is not sharded.
+    pub fn from_config(config: &ScriptCacheConfig) -> Self {
+        Self::empty()
+            .with_max_num_scripts(config.max_num_scripts)
+            .with_max_script_bytes(config.max_script_bytes)
+            .with_max_known_bad_scripts(config.max_known_bad_scripts)
+    }
+
+    /// Returns the same cache which stores at most the specified number of scripts, evicting the
+    /// oldest ones first. Already cached scripts are kept, as long as they fit.
+    pub fn with_max_num_scripts(mut self, max_num_scripts: usize) -> Self {
+        self.max_num_scripts = Some(max_num_scripts);
+        self.oversized_scripts
+            .get_mut()
+            .set_capacity(max_num_scripts);
+        let script_cache = self.script_cache.get_mut();
+        let insertion_order = self.insertion_order.get_mut();
+        while insertion_order.len() > max_num_scripts {
+            if let Some(oldest_key) = insertion_order.pop_front() {
+                script_cache.remove(&oldest_key);
+            }
+        }
+        self
+    }
+
+    /// Returns the same cache which does not cache scripts larger than the specified number of
+    /// bytes.
+    pub fn with_max_script_bytes(mut self, max_script_bytes: usize) -> Self {
+        self.max_script_bytes = Some(max_script_bytes);
+        self
+    }
+
+    /// Returns the same cache which additionally remembers the verification errors of up to the
+    /// specified number of scripts that failed verification (evicting the oldest ones first).
+    /// Already remembered scripts are kept, as long as they fit.
+    pub fn with_max_known_bad_scripts(mut self, max_known_bad_scripts: usize) -> Self {
+        match &mut self.known_bad_scripts {
+            Some(known_bad_scripts) => known_bad_scripts
+                .get_mut()
+                .set_capacity(max_known_bad_scripts),
+            None => {
+                self.known_bad_scripts =
+                    Some(RefCell::new(KnownBadScripts::new(max_known_bad_scripts)))
+            },
         }
+        self
+    }
+
+    /// Returns the same cache which computes the external hashes of serialized scripts with the
+    /// given hasher.
+    pub fn with_script_hasher(mut self, script_hasher: Arc<dyn ScriptHasher>) -> Self {
+        self.script_hasher = script_hasher;
+        self
+    }
+
+    /// Evicts the oldest scripts so that one more script fits into the cache, and records the key
+    /// of that script as the newest one. Returns false if the cache cannot store any scripts.
+    fn make_space(&self, script_cache: &mut HashMap<K, Code<D, V>>, key: &K) -> bool {
+        let mut insertion_order = self.insertion_order.borrow_mut();
+        if let Some(max_num_scripts) = self.max_num_scripts {
+            if max_num_scripts == 0 {
+                return false;
+            }
+            while insertion_order.len() >= max_num_scripts {
+                if let Some(oldest_key) = insertion_order.pop_front() {
+                    script_cache.remove(&oldest_key);
+                }
+            }
+        }
+        insertion_order.push_back(key.clone());
+        true
     }
 }
 
@@ -74,7 +400,12 @@ where
     ) -> Arc<Self::Deserialized> {
         use hashbrown::hash_map::Entry::*;
 
-        match self.script_cache.borrow_mut().entry(key) {
+        let mut script_cache = self.script_cache.borrow_mut();
+        if !script_cache.contains_key(&key) && !self.make_space(&mut script_cache, &key) {
+            // No scripts can be cached, so the script is returned without being cached.
+            return Arc::new(deserialized_script);
+        }
+        match script_cache.entry(key) {
             Occupied(entry) => entry.get().deserialized().clone(),
             Vacant(entry) => entry
                 .insert(Code::from_deserialized(deserialized_script))
@@ -90,7 +421,11 @@ where
     ) -> Arc<Self::Verified> {
         use hashbrown::hash_map::Entry::*;
 
-        match self.script_cache.borrow_mut().entry(key) {
+        let mut script_cache = self.script_cache.borrow_mut();
+        if !script_cache.contains_key(&key) && !self.make_space(&mut script_cache, &key) {
+            return Arc::new(verified_script);
+        }
+        match script_cache.entry(key) {
             Occupied(mut entry) => {
                 if !entry.get().is_verified() {
                     let new_script = Code::from_verified(verified_script);
@@ -115,11 +450,46 @@ where
     fn num_scripts(&self) -> usize {
         self.script_cache.borrow().len()
     }
+
+    fn max_script_bytes(&self) -> Option<usize> {
+        self.max_script_bytes
+    }
+
+    fn insert_oversized_script(&self, key: Self::Key) -> bool {
+        self.oversized_scripts.borrow_mut().insert(key, ())
+    }
+
+    fn num_oversized_scripts(&self) -> usize {
+        self.oversized_scripts.borrow().len()
+    }
+
+    fn insert_known_bad_script(&self, key: Self::Key, error: VMError) {
+        if let Some(known_bad_scripts) = &self.known_bad_scripts {
+            known_bad_scripts.borrow_mut().insert(key, error);
+        }
+    }
+
+    fn get_known_bad_script(&self, key: &Self::Key) -> Option<VMError> {
+        self.known_bad_scripts.as_ref()?.borrow().get(key)
+    }
+
+    fn script_hasher(&self) -> &dyn ScriptHasher {
+        self.script_hasher.as_ref()
+    }
 }
 
 /// [Sync] implementation of script cache suitable for multithreaded execution.
 pub struct SyncScriptCache<K, D, V> {
     script_cache: DashMap<K, CachePadded<Code<D, V>>>,
+    /// Keys of cached scripts, from the oldest to the newest, used for eviction.
+    insertion_order: Mutex<VecDeque<K>>,
+    max_num_scripts: Option<usize>,
+    max_script_bytes: Option<usize>,
+    /// Oversized scripts, remembered up to the maximum number of scripts.
+    oversized_scripts: Mutex<OversizedScripts<K>>,
+    known_bad_scripts: Option<Mutex<KnownBadScripts<K>>>,
+    script_hasher: Arc<dyn ScriptHasher>,
+    in_flight_verifications: DashMap<K, Arc<InFlightVerification>>,
 }
 
 impl<K, D, V> SyncScriptCache<K, D, V>
@@ -131,10 +501,192 @@ where
     pub fn empty() -> Self {
         Self {
             script_cache: DashMap::new(),
+            insertion_order: Mutex::new(VecDeque::new()),
+            max_num_scripts: None,
+            max_script_bytes: None,
+            oversized_scripts: Mutex::new(OversizedScripts::new(usize::MAX)),
+            known_bad_scripts: None,
+            script_hasher: Arc::new(Sha3_256ScriptHasher),
+            in_flight_verifications: DashMap::new(),
+        }
+    }
+
+    /// Returns an empty script cache with the bounds and the number of shards of the given
+    /// configuration. Returns an error if the number of shards is not a power of two greater
+    /// than 1.
+    pub fn from_config(config: &ScriptCacheConfig) -> VMResult<Self> {
+        if config.num_shards <= 1 || !config.num_shards.is_power_of_two() {
+            return Err(PartialVMError::new(StatusCode::VM_STARTUP_FAILURE)
+                .with_message(format!(
+                    "Number of script cache shards must be a power of two greater than 1, got {}",
+                    config.num_shards
+                ))
+                .finish(Location::Undefined));
+        }
+
+        let script_cache = Self {
+            script_cache: DashMap::with_shard_amount(config.num_shards),
+            ..Self::empty()
+        };
+        Ok(script_cache
+            .with_max_num_scripts(config.max_num_scripts)
+            .with_max_script_bytes(config.max_script_bytes)
+            .with_max_known_bad_scripts(config.max_known_bad_scripts))
+    }
+
+    /// Returns the same cache which stores at most the specified number of scripts, evicting the
+    /// oldest ones first. Already cached scripts are kept, as long as they fit. When scripts are
+    /// inserted concurrently, the bound can be exceeded briefly until the evictions catch up.
+    pub fn with_max_num_scripts(mut self, max_num_scripts: usize) -> Self {
+        self.max_num_scripts = Some(max_num_scripts);
+        self.oversized_scripts
+            .get_mut()
+            .expect("Oversized scripts lock should not be poisoned")
+            .set_capacity(max_num_scripts);
+        self.evict_oldest_scripts();
+        self
+    }
+
+    /// Returns the same cache which does not cache scripts larger than the specified number of
+    /// bytes.
+    pub fn with_max_script_bytes(mut self, max_script_bytes: usize) -> Self {
+        self.max_script_bytes = Some(max_script_bytes);
+        self
+    }
+
+    /// Returns the same cache which additionally remembers the verification errors of up to the
+    /// specified number of scripts that failed verification (evicting the oldest ones first).
+    /// Already remembered scripts are kept, as long as they fit.
+    pub fn with_max_known_bad_scripts(mut self, max_known_bad_scripts: usize) -> Self {
+        match &mut self.known_bad_scripts {
+            Some(known_bad_scripts) => known_bad_scripts
+                .get_mut()
+                .expect("Known bad scripts lock should not be poisoned")
+                .set_capacity(max_known_bad_scripts),
+            None => {
+                self.known_bad_scripts =
+                    Some(Mutex::new(KnownBadScripts::new(max_known_bad_scripts)))
+            },
+        }
+        self
+    }
+
+    /// Returns the same cache which computes the external hashes of serialized scripts with the
+    /// given hasher.
+    pub fn with_script_hasher(mut self, script_hasher: Arc<dyn ScriptHasher>) -> Self {
+        self.script_hasher = script_hasher;
+        self
+    }
+
+    /// Records the key of a newly cached script as the newest one and evicts the oldest scripts
+    /// if the cache stores too many. Must not be called while holding a reference into the map,
+    /// as eviction locks its shards.
+    fn record_insertion(&self, key: K) {
+        self.insertion_order
+            .lock()
+            .expect("Script insertion order lock should not be poisoned")
+            .push_back(key);
+        self.evict_oldest_scripts();
+    }
+
+    /// Evicts the oldest scripts until the cache stores no more than the maximum number of them.
+    fn evict_oldest_scripts(&self) {
+        let max_num_scripts = match self.max_num_scripts {
+            Some(max_num_scripts) => max_num_scripts,
+            None => return,
+        };
+        let mut insertion_order = self
+            .insertion_order
+            .lock()
+            .expect("Script insertion order lock should not be poisoned");
+        while insertion_order.len() > max_num_scripts {
+            if let Some(oldest_key) = insertion_order.pop_front() {
+                self.script_cache.remove(&oldest_key);
+            }
         }
     }
 }
 
+impl<K, D, V> SyncScriptCache<K, D, V>
+where
+    K: Eq + Hash + Clone + Send + Sync + 'static,
+    D: Send + Sync + 'static,
+    V: Deref<Target = Arc<D>> + Send + Sync + 'static,
+{
+    /// If the script associated with the key is cached but not yet verified, hands a task which
+    /// verifies it with the provided function to `spawn` (e.g., to run it on a thread pool). Once
+    /// the task finishes, the verified script is cached. Returns true if the task was spawned,
+    /// and false if the script is not cached, already verified, or already being verified in
+    /// background. If the verification fails, nothing is cached and the error is reported when
+    /// the script is verified again in the foreground.
+    ///
+    /// Callers of [ScriptCache::wait_for_background_verification] only block once the task has
+    /// started running, and verify the script themselves before that. So `spawn` may queue the
+    /// task on a thread pool whose threads also wait for background verifications, but `verify`
+    /// must not wait for background verifications of this cache itself, and must not block on
+    /// the threads waiting for it.
+    pub fn verify_in_background(
+        self: &Arc<Self>,
+        key: &K,
+        verify: Arc<dyn Fn(Arc<D>) -> VMResult<V> + Send + Sync>,
+        spawn: impl FnOnce(Box<dyn FnOnce() + Send>),
+    ) -> bool {
+        use dashmap::mapref::entry::Entry::*;
+
+        let deserialized_script = match self.get_script(key) {
+            Some(Code::Deserialized(deserialized_script)) => deserialized_script,
+            Some(Code::Verified(_)) | None => return false,
+        };
+        let in_flight_verification = match self.in_flight_verifications.entry(key.clone()) {
+            Occupied(_) => return false,
+            Vacant(entry) => entry
+                .insert(Arc::new(InFlightVerification::default()))
+                .clone(),
+        };
+
+        // The guard marks the verification as finished even if it panics or the task is dropped
+        // without being run, so that waiters are never blocked forever.
+        let guard = InFlightVerificationGuard {
+            script_cache: self.clone(),
+            key: key.clone(),
+            in_flight_verification,
+        };
+        spawn(Box::new(move || {
+            guard.in_flight_verification.start();
+            if let Ok(verified_script) = verify(deserialized_script) {
+                guard
+                    .script_cache
+                    .insert_verified_script(guard.key.clone(), verified_script);
+            }
+            // The script is cached before the guard is dropped and the verification is marked as
+            // finished, so that all waiters observe the verified script.
+            drop(guard);
+        }));
+        true
+    }
+}
+
+/// Removes a background verification from the in-flight verifications of the cache and marks it
+/// as finished when dropped.
+struct InFlightVerificationGuard<K, D, V>
+where
+    K: Eq + Hash,
+{
+    script_cache: Arc<SyncScriptCache<K, D, V>>,
+    key: K,
+    in_flight_verification: Arc<InFlightVerification>,
+}
+
+impl<K, D, V> Drop for InFlightVerificationGuard<K, D, V>
+where
+    K: Eq + Hash,
+{
+    fn drop(&mut self) {
+        self.script_cache.in_flight_verifications.remove(&self.key);
+        self.in_flight_verification.finish();
+    }
+}
+
 impl<K, D, V> ScriptCache for SyncScriptCache<K, D, V>
 where
     K: Eq + Hash + Clone,
@@ -153,12 +705,21 @@ where
 
         match self.script_cache.entry(key) {
             Occupied(entry) => entry.get().deserialized().clone(),
-            Vacant(entry) => entry
-                .insert(CachePadded::new(Code::from_deserialized(
-                    deserialized_script,
-                )))
-                .deserialized()
-                .clone(),
+            Vacant(entry) => {
+                if self.max_num_scripts == Some(0) {
+                    // No scripts can be cached, so the script is returned without being cached.
+                    return Arc::new(deserialized_script);
+                }
+                let key = entry.key().clone();
+                let deserialized_script = entry
+                    .insert(CachePadded::new(Code::from_deserialized(
+                        deserialized_script,
+                    )))
+                    .deserialized()
+                    .clone();
+                self.record_insertion(key);
+                deserialized_script
+            },
         }
     }
 
@@ -180,10 +741,18 @@ where
                     entry.get().verified().clone()
                 }
             },
-            Vacant(entry) => entry
-                .insert(CachePadded::new(Code::from_verified(verified_script)))
-                .verified()
-                .clone(),
+            Vacant(entry) => {
+                if self.max_num_scripts == Some(0) {
+                    return Arc::new(verified_script);
+                }
+                let key = entry.key().clone();
+                let verified_script = entry
+                    .insert(CachePadded::new(Code::from_verified(verified_script)))
+                    .verified()
+                    .clone();
+                self.record_insertion(key);
+                verified_script
+            },
         }
     }
 
@@ -195,6 +764,212 @@ where
     fn num_scripts(&self) -> usize {
         self.script_cache.len()
     }
+
+    fn max_script_bytes(&self) -> Option<usize> {
+        self.max_script_bytes
+    }
+
+    fn insert_oversized_script(&self, key: Self::Key) -> bool {
+        self.oversized_scripts
+            .lock()
+            .expect("Oversized scripts lock should not be poisoned")
+            .insert(key, ())
+    }
+
+    fn num_oversized_scripts(&self) -> usize {
+        self.oversized_scripts
+            .lock()
+            .expect("Oversized scripts lock should not be poisoned")
+            .len()
+    }
+
+    fn insert_known_bad_script(&self, key: Self::Key, error: VMError) {
+        if let Some(known_bad_scripts) = &self.known_bad_scripts {
+            known_bad_scripts
+                .lock()
+                .expect("Known bad scripts lock should not be poisoned")
+                .insert(key, error);
+        }
+    }
+
+    fn get_known_bad_script(&self, key: &Self::Key) -> Option<VMError> {
+        self.known_bad_scripts
+            .as_ref()?
+            .lock()
+            .expect("Known bad scripts lock should not be poisoned")
+            .get(key)
+    }
+
+    fn script_hasher(&self) -> &dyn ScriptHasher {
+        self.script_hasher.as_ref()
+    }
+
+    fn wait_for_background_verification(&self, key: &Self::Key) {
+        // Clone the entry so that the map is not locked while waiting.
+        let in_flight_verification = self
+            .in_flight_verifications
+            .get(key)
+            .map(|entry| entry.value().clone());
+        if let Some(in_flight_verification) = in_flight_verification {
+            in_flight_verification.wait();
+        }
+    }
+}
+
+/// A single layer of [LayeredScriptCache].
+pub type ScriptCacheLayer<K, D, V> = Box<dyn ScriptCache<Key = K, Deserialized = D, Verified = V>>;
+
+/// Script cache composed of multiple layers, ordered from the fastest (e.g., a small local cache)
+/// to the slowest one (e.g., a large shared cache). Lookups check the layers in order, and scripts
+/// found in a slower layer are copied into all faster layers. Insertions go to every layer.
+pub struct LayeredScriptCache<K, D, V> {
+    layers: Vec<ScriptCacheLayer<K, D, V>>,
+    script_hasher: Arc<dyn ScriptHasher>,
+}
+
+impl<K, D, V> LayeredScriptCache<K, D, V>
+where
+    K: Eq + Hash + Clone,
+    D: Clone,
+    V: Deref<Target = Arc<D>> + Clone,
+{
+    /// Returns a cache composed of the given layers, starting from the fastest one, whose external
+    /// script hashes are computed with the given hasher. The hashers of the layers themselves are
+    /// not used. Panics if there are no layers.
+    pub fn new(
+        layers: Vec<ScriptCacheLayer<K, D, V>>,
+        script_hasher: Arc<dyn ScriptHasher>,
+    ) -> Self {
+        assert!(!layers.is_empty(), "Layered script cache must have layers");
+        Self {
+            layers,
+            script_hasher,
+        }
+    }
+
+    /// Returns all layers of this cache, starting from the fastest one.
+    pub fn layers(&self) -> &[ScriptCacheLayer<K, D, V>] {
+        &self.layers
+    }
+
+    /// Copies the script into the given (faster) layer.
+    fn promote(layer: &ScriptCacheLayer<K, D, V>, key: &K, script: &Code<D, V>) {
+        match script {
+            Code::Deserialized(deserialized_script) => {
+                layer.insert_deserialized_script(key.clone(), deserialized_script.as_ref().clone());
+            },
+            Code::Verified(verified_script) => {
+                layer.insert_verified_script(key.clone(), verified_script.as_ref().clone());
+            },
+        }
+    }
+}
+
+impl<K, D, V> ScriptCache for LayeredScriptCache<K, D, V>
+where
+    K: Eq + Hash + Clone,
+    D: Clone,
+    V: Deref<Target = Arc<D>> + Clone,
+{
+    type Deserialized = D;
+    type Key = K;
+    type Verified = V;
+
+    fn insert_deserialized_script(
+        &self,
+        key: Self::Key,
+        deserialized_script: Self::Deserialized,
+    ) -> Arc<Self::Deserialized> {
+        // Insert into the slowest layer first, so that faster layers are consistent with it in
+        // case it already stores the script.
+        let (slowest_layer, faster_layers) = self.layers.split_last().expect("Layers exist");
+        let mut script = slowest_layer.insert_deserialized_script(key.clone(), deserialized_script);
+        for layer in faster_layers.iter().rev() {
+            script = layer.insert_deserialized_script(key.clone(), script.as_ref().clone());
+        }
+        script
+    }
+
+    fn insert_verified_script(
+        &self,
+        key: Self::Key,
+        verified_script: Self::Verified,
+    ) -> Arc<Self::Verified> {
+        let (slowest_layer, faster_layers) = self.layers.split_last().expect("Layers exist");
+        let mut script = slowest_layer.insert_verified_script(key.clone(), verified_script);
+        for layer in faster_layers.iter().rev() {
+            script = layer.insert_verified_script(key.clone(), script.as_ref().clone());
+        }
+        script
+    }
+
+    fn get_script(&self, key: &Self::Key) -> Option<Code<Self::Deserialized, Self::Verified>> {
+        for (idx, layer) in self.layers.iter().enumerate() {
+            if let Some(script) = layer.get_script(key) {
+                for faster_layer in &self.layers[..idx] {
+                    Self::promote(faster_layer, key, &script);
+                }
+                return Some(script);
+            }
+        }
+        None
+    }
+
+    /// Returns the largest number of scripts stored by a single layer.
+    fn num_scripts(&self) -> usize {
+        self.layers
+            .iter()
+            .map(|layer| layer.num_scripts())
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Returns the smallest limit on the size of cached scripts among all layers.
+    fn max_script_bytes(&self) -> Option<usize> {
+        self.layers
+            .iter()
+            .filter_map(|layer| layer.max_script_bytes())
+            .min()
+    }
+
+    /// Records the script in every layer, returning true if any of them has not recorded it
+    /// before.
+    fn insert_oversized_script(&self, key: Self::Key) -> bool {
+        self.layers.iter().fold(false, |inserted, layer| {
+            layer.insert_oversized_script(key.clone()) || inserted
+        })
+    }
+
+    /// Returns the largest number of oversized scripts remembered by a single layer.
+    fn num_oversized_scripts(&self) -> usize {
+        self.layers
+            .iter()
+            .map(|layer| layer.num_oversized_scripts())
+            .max()
+            .unwrap_or(0)
+    }
+
+    fn insert_known_bad_script(&self, key: Self::Key, error: VMError) {
+        for layer in &self.layers {
+            layer.insert_known_bad_script(key.clone(), error.clone());
+        }
+    }
+
+    fn get_known_bad_script(&self, key: &Self::Key) -> Option<VMError> {
+        self.layers
+            .iter()
+            .find_map(|layer| layer.get_known_bad_script(key))
+    }
+
+    fn script_hasher(&self) -> &dyn ScriptHasher {
+        self.script_hasher.as_ref()
+    }
+
+    fn wait_for_background_verification(&self, key: &Self::Key) {
+        for layer in &self.layers {
+            layer.wait_for_background_verification(key);
+        }
+    }
 }
 
 #[cfg(test)]
@@ -202,7 +977,13 @@ mod tests {
     use super::*;
     use crate::code::{MockDeserializedCode, MockVerifiedCode};
     use claims::{assert_ok, assert_some};
-    use std::collections::BTreeSet;
+    use std::{
+        collections::BTreeSet,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            mpsc,
+        },
+    };
 
     fn insert_deserialized_test_case(
         script_cache: &impl ScriptCache<
@@ -319,6 +1100,295 @@ mod tests {
         test_get_script_test_case(&SyncScriptCache::empty());
     }
 
+    fn known_bad_scripts_test_case(
+        script_cache: &impl ScriptCache<
+            Key = usize,
+            Deserialized = MockDeserializedCode,
+            Verified = MockVerifiedCode,
+        >,
+    ) {
+        let error = |key| {
+            PartialVMError::new(StatusCode::NEGATIVE_STACK_SIZE_WITHIN_BLOCK)
+                .with_message(format!("script {}", key))
+                .finish(Location::Script)
+        };
+
+        script_cache.insert_known_bad_script(1, error(1));
+        script_cache.insert_known_bad_script(2, error(2));
+        assert_eq!(
+            assert_some!(script_cache.get_known_bad_script(&1)),
+            error(1)
+        );
+        assert!(script_cache.is_known_bad(&2));
+
+        // Known bad scripts are not counted as cached scripts.
+        assert_eq!(script_cache.num_scripts(), 0);
+
+        // Re-inserting keeps the original error, and does not refresh the entry.
+        script_cache.insert_known_bad_script(1, error(100));
+        assert_eq!(
+            assert_some!(script_cache.get_known_bad_script(&1)),
+            error(1)
+        );
+
+        // The oldest entry is evicted once the capacity is reached.
+        script_cache.insert_known_bad_script(3, error(3));
+        assert!(!script_cache.is_known_bad(&1));
+        assert!(script_cache.is_known_bad(&2));
+        assert!(script_cache.is_known_bad(&3));
+    }
+
+    #[test]
+    fn test_known_bad_scripts() {
+        known_bad_scripts_test_case(&UnsyncScriptCache::empty().with_max_known_bad_scripts(2));
+        known_bad_scripts_test_case(&SyncScriptCache::empty().with_max_known_bad_scripts(2));
+    }
+
+    fn oversized_scripts_test_case(
+        script_cache: &impl ScriptCache<
+            Key = usize,
+            Deserialized = MockDeserializedCode,
+            Verified = MockVerifiedCode,
+        >,
+    ) {
+        // Every script is recorded once.
+        assert!(script_cache.insert_oversized_script(0));
+        assert!(!script_cache.insert_oversized_script(0));
+        assert!(script_cache.insert_oversized_script(1));
+        assert_eq!(script_cache.num_oversized_scripts(), 2);
+
+        // Once full, the oldest scripts are forgotten first.
+        assert!(script_cache.insert_oversized_script(2));
+        assert_eq!(script_cache.num_oversized_scripts(), 2);
+        assert!(script_cache.insert_oversized_script(0));
+        assert!(!script_cache.insert_oversized_script(2));
+    }
+
+    #[test]
+    fn test_oversized_scripts() {
+        oversized_scripts_test_case(&UnsyncScriptCache::empty().with_max_num_scripts(2));
+        oversized_scripts_test_case(&SyncScriptCache::empty().with_max_num_scripts(2));
+    }
+
+    #[test]
+    fn test_known_bad_scripts_disabled() {
+        let script_cache =
+            UnsyncScriptCache::<usize, MockDeserializedCode, MockVerifiedCode>::empty();
+        let error =
+            PartialVMError::new(StatusCode::UNKNOWN_VERIFICATION_ERROR).finish(Location::Script);
+        script_cache.insert_known_bad_script(1, error);
+        assert!(!script_cache.is_known_bad(&1));
+    }
+
+    fn script_cache_config_test_case(
+        script_cache: &impl ScriptCache<
+            Key = usize,
+            Deserialized = MockDeserializedCode,
+            Verified = MockVerifiedCode,
+        >,
+        config: &ScriptCacheConfig,
+    ) {
+        assert_eq!(
+            script_cache.max_script_bytes(),
+            Some(config.max_script_bytes)
+        );
+
+        for key in 0..config.max_num_scripts {
+            script_cache.insert_deserialized_script(key, MockDeserializedCode::new(key));
+        }
+        assert_eq!(script_cache.num_scripts(), config.max_num_scripts);
+
+        // Once the cache is full, new scripts evict the oldest ones.
+        let key = config.max_num_scripts;
+        let deserialized_script =
+            script_cache.insert_deserialized_script(key, MockDeserializedCode::new(key));
+        assert_eq!(deserialized_script.value(), key);
+        let verified_script = script_cache.insert_verified_script(key, MockVerifiedCode::new(key));
+        assert_eq!(verified_script.value(), key);
+        let script = assert_some!(script_cache.get_script(&key));
+        assert!(matches!(script, Code::Verified(s) if s.value() == key));
+        assert!(script_cache.get_script(&0).is_none());
+        assert_eq!(script_cache.num_scripts(), config.max_num_scripts);
+
+        // Already cached scripts can still be verified without evicting anything.
+        script_cache.insert_verified_script(1, MockVerifiedCode::new(1));
+        let script = assert_some!(script_cache.get_script(&1));
+        assert!(matches!(script, Code::Verified(s) if s.value() == 1));
+        assert_some!(script_cache.get_script(&2));
+        assert_eq!(script_cache.num_scripts(), config.max_num_scripts);
+
+        let error =
+            PartialVMError::new(StatusCode::UNKNOWN_VERIFICATION_ERROR).finish(Location::Script);
+        for key in 0..=config.max_known_bad_scripts {
+            script_cache.insert_known_bad_script(key, error.clone());
+        }
+        assert!(!script_cache.is_known_bad(&0));
+        assert!(script_cache.is_known_bad(&config.max_known_bad_scripts));
+    }
+
+    #[test]
+    fn test_script_cache_config_presets() {
+        for config in [
+            ScriptCacheConfig::mainnet(),
+            ScriptCacheConfig::testnet(),
+            ScriptCacheConfig::local(),
+        ] {
+            script_cache_config_test_case(&UnsyncScriptCache::from_config(&config), &config);
+            script_cache_config_test_case(
+                &assert_ok!(SyncScriptCache::from_config(&config)),
+                &config,
+            );
+        }
+    }
+
+    #[test]
+    fn test_with_max_num_scripts_evicts_oldest_scripts() {
+        let script_cache = UnsyncScriptCache::<_, _, MockVerifiedCode>::empty();
+        for key in 0..4 {
+            script_cache.insert_deserialized_script(key, MockDeserializedCode::new(key));
+        }
+        let script_cache = script_cache.with_max_num_scripts(2);
+        assert_eq!(script_cache.num_scripts(), 2);
+        assert!(script_cache.get_script(&1).is_none());
+        assert_some!(script_cache.get_script(&2));
+
+        let script_cache = SyncScriptCache::<_, _, MockVerifiedCode>::empty();
+        for key in 0..4 {
+            script_cache.insert_deserialized_script(key, MockDeserializedCode::new(key));
+        }
+        let script_cache = script_cache.with_max_num_scripts(2);
+        assert_eq!(script_cache.num_scripts(), 2);
+        assert!(script_cache.get_script(&1).is_none());
+        assert_some!(script_cache.get_script(&2));
+    }
+
+    #[test]
+    fn test_script_cache_config_invalid_num_shards() {
+        for num_shards in [0, 1, 3, 100] {
+            let config = ScriptCacheConfig {
+                num_shards,
+                ..ScriptCacheConfig::local()
+            };
+            let script_cache =
+                SyncScriptCache::<usize, MockDeserializedCode, MockVerifiedCode>::from_config(
+                    &config,
+                );
+            let error = assert_some!(script_cache.err());
+            assert_eq!(error.major_status(), StatusCode::VM_STARTUP_FAILURE);
+        }
+    }
+
+    #[test]
+    fn test_resize_known_bad_scripts() {
+        let error =
+            PartialVMError::new(StatusCode::UNKNOWN_VERIFICATION_ERROR).finish(Location::Script);
+        let script_cache =
+            UnsyncScriptCache::<usize, MockDeserializedCode, MockVerifiedCode>::empty()
+                .with_max_known_bad_scripts(3);
+        for key in 0..3 {
+            script_cache.insert_known_bad_script(key, error.clone());
+        }
+
+        // Shrinking keeps the most recent entries.
+        let script_cache = script_cache.with_max_known_bad_scripts(2);
+        assert!(!script_cache.is_known_bad(&0));
+        assert!(script_cache.is_known_bad(&1));
+        assert!(script_cache.is_known_bad(&2));
+
+        let script_cache = script_cache.with_max_known_bad_scripts(3);
+        script_cache.insert_known_bad_script(3, error);
+        assert!(script_cache.is_known_bad(&1));
+        assert!(script_cache.is_known_bad(&3));
+    }
+
+    fn layered_script_cache(
+        fast_layer: UnsyncScriptCache<usize, MockDeserializedCode, MockVerifiedCode>,
+        slow_layer: UnsyncScriptCache<usize, MockDeserializedCode, MockVerifiedCode>,
+    ) -> LayeredScriptCache<usize, MockDeserializedCode, MockVerifiedCode> {
+        LayeredScriptCache::new(
+            vec![Box::new(fast_layer), Box::new(slow_layer)],
+            Arc::new(Sha3_256ScriptHasher),
+        )
+    }
+
+    #[test]
+    fn test_layered_script_cache() {
+        insert_deserialized_test_case(&layered_script_cache(
+            UnsyncScriptCache::empty(),
+            UnsyncScriptCache::empty(),
+        ));
+        insert_verified_test_case(&layered_script_cache(
+            UnsyncScriptCache::empty(),
+            UnsyncScriptCache::empty(),
+        ));
+        test_get_script_test_case(&layered_script_cache(
+            UnsyncScriptCache::empty(),
+            UnsyncScriptCache::empty(),
+        ));
+    }
+
+    #[test]
+    fn test_layered_script_cache_promotes_scripts() {
+        let slow_layer = UnsyncScriptCache::empty();
+        slow_layer.insert_deserialized_script(1, MockDeserializedCode::new(1));
+        slow_layer.insert_verified_script(2, MockVerifiedCode::new(2));
+
+        let script_cache = layered_script_cache(UnsyncScriptCache::empty(), slow_layer);
+        let fast_layer = &script_cache.layers()[0];
+        assert_eq!(fast_layer.num_scripts(), 0);
+        assert_eq!(script_cache.num_scripts(), 2);
+
+        // A hit in the slow layer copies the script into the fast layer.
+        let script_1 = assert_some!(script_cache.get_script(&1));
+        assert!(matches!(script_1, Code::Deserialized(s) if s.value() == 1));
+        let script_1 = assert_some!(fast_layer.get_script(&1));
+        assert!(matches!(script_1, Code::Deserialized(s) if s.value() == 1));
+
+        // Verified scripts stay verified when promoted.
+        let script_2 = assert_some!(script_cache.get_script(&2));
+        assert!(matches!(script_2, Code::Verified(s) if s.value() == 2));
+        let script_2 = assert_some!(fast_layer.get_script(&2));
+        assert!(matches!(script_2, Code::Verified(s) if s.value() == 2));
+        assert_eq!(fast_layer.num_scripts(), 2);
+
+        // Insertions go to all layers.
+        script_cache.insert_verified_script(3, MockVerifiedCode::new(3));
+        for layer in script_cache.layers() {
+            let script_3 = assert_some!(layer.get_script(&3));
+            assert!(matches!(script_3, Code::Verified(s) if s.value() == 3));
+        }
+        assert!(script_cache.get_script(&4).is_none());
+    }
+
+    /// Uses the first byte of the script as its hash.
+    struct FirstByteHasher;
+
+    impl ScriptHasher for FirstByteHasher {
+        fn hash(&self, bytes: &[u8]) -> [u8; 32] {
+            [bytes[0]; 32]
+        }
+    }
+
+    #[test]
+    fn test_layered_script_cache_hasher() {
+        type Layer = UnsyncScriptCache<usize, MockDeserializedCode, MockVerifiedCode>;
+        type Layered = LayeredScriptCache<usize, MockDeserializedCode, MockVerifiedCode>;
+        let bytes = [7, 1, 2];
+
+        // The hasher of the layered cache is used, whatever the hashers of its layers.
+        let script_cache = Layered::new(
+            vec![
+                Box::new(Layer::empty()),
+                Box::new(Layer::empty().with_script_hasher(Arc::new(FirstByteHasher))),
+            ],
+            Arc::new(Sha3_256ScriptHasher),
+        );
+        assert_eq!(script_cache.script_hasher().hash(&bytes), sha3_256(&bytes));
+
+        let script_cache = Layered::new(vec![Box::new(Layer::empty())], Arc::new(FirstByteHasher));
+        assert_eq!(script_cache.script_hasher().hash(&bytes), [7; 32]);
+    }
+
     #[test]
     fn test_sync_insert_deserialized_multithreaded() {
         let script_cache = Arc::new(SyncScriptCache::<usize, _, MockVerifiedCode>::empty());
@@ -384,4 +1454,77 @@ mod tests {
         let script = assert_some!(script_cache.get_script(&key));
         assert!(matches!(script, Code::Verified(s) if s.value() == 8));
     }
+
+    #[test]
+    fn test_sync_verify_in_background() {
+        let script_cache = Arc::new(SyncScriptCache::<usize, _, _>::empty());
+        script_cache.insert_deserialized_script(1, MockDeserializedCode::new(1));
+        script_cache.insert_verified_script(2, MockVerifiedCode::new(2));
+
+        let num_verifications = Arc::new(AtomicUsize::new(0));
+        let (finish_tx, finish_rx) = mpsc::channel::<()>();
+        let finish_rx = Mutex::new(finish_rx);
+        let verify: Arc<
+            dyn Fn(Arc<MockDeserializedCode>) -> VMResult<MockVerifiedCode> + Send + Sync,
+        > = Arc::new({
+            let num_verifications = num_verifications.clone();
+            move |deserialized_script| {
+                num_verifications.fetch_add(1, Ordering::SeqCst);
+                // Keep the verification in flight until the test lets it finish.
+                finish_rx.lock().unwrap().recv().unwrap();
+                Ok(MockVerifiedCode::new(deserialized_script.value()))
+            }
+        });
+        let spawn = |task: Box<dyn FnOnce() + Send>| {
+            std::thread::spawn(task);
+        };
+
+        // Missing and already verified scripts are not verified in background.
+        assert!(!script_cache.verify_in_background(&2, verify.clone(), spawn));
+        assert!(!script_cache.verify_in_background(&3, verify.clone(), spawn));
+
+        assert!(script_cache.verify_in_background(&1, verify.clone(), spawn));
+        // The verification is in flight, so it is not started again.
+        assert!(!script_cache.verify_in_background(&1, verify.clone(), spawn));
+
+        let waiter = std::thread::spawn({
+            let script_cache = script_cache.clone();
+            move || {
+                script_cache.wait_for_background_verification(&1);
+                script_cache.get_script(&1)
+            }
+        });
+        finish_tx.send(()).unwrap();
+
+        // Whether the waiter started waiting before or after the verification finished, it must
+        // observe the verified script.
+        let script = assert_some!(assert_ok!(waiter.join()));
+        assert!(matches!(script, Code::Verified(s) if s.value() == 1));
+        assert_eq!(num_verifications.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_sync_wait_for_pending_background_verification() {
+        let script_cache = Arc::new(SyncScriptCache::<usize, _, _>::empty());
+        script_cache.insert_deserialized_script(1, MockDeserializedCode::new(1));
+
+        let verify: Arc<
+            dyn Fn(Arc<MockDeserializedCode>) -> VMResult<MockVerifiedCode> + Send + Sync,
+        > = Arc::new(|deserialized_script| Ok(MockVerifiedCode::new(deserialized_script.value())));
+        let queued_task = Mutex::new(None);
+        assert!(script_cache.verify_in_background(&1, verify, |task| {
+            *queued_task.lock().unwrap() = Some(task);
+        }));
+
+        // The task has not started, e.g., because it is queued behind the waiter on the same
+        // thread pool, so the waiter returns right away to verify the script itself.
+        script_cache.wait_for_background_verification(&1);
+        let script = assert_some!(script_cache.get_script(&1));
+        assert!(!script.is_verified());
+
+        let task = assert_some!(queued_task.lock().unwrap().take());
+        task();
+        let script = assert_some!(script_cache.get_script(&1));
+        assert!(matches!(script, Code::Verified(s) if s.value() == 1));
+    }
 }
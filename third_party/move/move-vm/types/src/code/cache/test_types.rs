@@ -17,7 +17,7 @@ impl MockDeserializedCode {
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct MockVerifiedCode(Arc<MockDeserializedCode>);
 
 impl MockVerifiedCode {
@@ -15,7 +15,10 @@ pub use cache::{
         ambassador_impl_ModuleCache, ModuleCache, ModuleCode, ModuleCodeBuilder, SyncModuleCache,
         UnsyncModuleCache,
     },
-    script_cache::{ambassador_impl_ScriptCache, ScriptCache, SyncScriptCache, UnsyncScriptCache},
+    script_cache::{
+        ambassador_impl_ScriptCache, LayeredScriptCache, ScriptCache, ScriptCacheConfig,
+        ScriptCacheLayer, ScriptHasher, Sha3_256ScriptHasher, SyncScriptCache, UnsyncScriptCache,
+    },
     types::{Code, WithAddress, WithBytes, WithHash, WithName, WithSize},
 };
 pub use storage::ModuleBytesStorage;
@@ -2,6 +2,7 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use crate::on_chain_config::BlockGasLimitType;
+use move_vm_types::code::ScriptCacheConfig;
 use serde::{Deserialize, Serialize};
 
 /// Local, per-node configurations for module cache. While caches can be persisted across multiple
@@ -16,6 +17,8 @@ pub struct BlockExecutorModuleCacheLocalConfig {
     /// The maximum size (in terms of entries) of struct name re-indexing map stored in the runtime
     /// environment.
     pub max_struct_name_index_map_num_entries: usize,
+    /// Bounds and the number of shards of the per-block script cache used by parallel execution.
+    pub script_cache_config: ScriptCacheConfig,
 }
 
 impl Default for BlockExecutorModuleCacheLocalConfig {
@@ -26,6 +29,7 @@ impl Default for BlockExecutorModuleCacheLocalConfig {
             // of writing this comment, 13.11.24).
             max_module_cache_size_in_bytes: 1024 * 1024 * 1024,
             max_struct_name_index_map_num_entries: 1_000_000,
+            script_cache_config: ScriptCacheConfig::mainnet(),
         }
     }
 }
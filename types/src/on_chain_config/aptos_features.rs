@@ -104,6 +104,10 @@ pub enum FeatureFlag {
     /// that results in a new package created but without any code. With this feature, it is no
     /// longer possible and an explicit error is returned if publishing is attempted.
     DISALLOW_INIT_MODULE_TO_PUBLISH_MODULES = 82,
+    /// Allows publishing modules carrying the compiler experiments metadata entry, which records
+    /// the optimizations the module was compiled with. Without it, such modules are rejected as
+    /// having unknown metadata.
+    ACCEPT_EXPERIMENTS_METADATA = 83,
 }
 
 impl FeatureFlag {